@@ -0,0 +1,292 @@
+// NES
+use mem::Memory as Mem;
+use enums::{IrqSource, Region};
+use cdl::PRG_PCM;
+
+// std
+use std::fmt;
+use std::num::Wrapping as W;
+
+// Only what drives the IRQ line is emulated for now: the frame counter and
+// the DMC sample reader. The output is silence until the channels exist.
+
+// Output samples per second
+pub const SAMPLE_RATE           : u32 = 44100;
+// A second of samples, kept when no frontend takes them
+const MAX_SAMPLES               : usize = SAMPLE_RATE as usize;
+
+const STATUS_DMC_ACTIVE         : u8 = 0x10;
+const STATUS_FRAME_IRQ          : u8 = 0x40;
+const STATUS_DMC_IRQ            : u8 = 0x80;
+
+const FRAME_MODE_5_STEP         : u8 = 0x80;
+const FRAME_IRQ_INHIBIT         : u8 = 0x40;
+
+const DMC_IRQ_ENABLE            : u8 = 0x80;
+const DMC_LOOP                  : u8 = 0x40;
+
+struct FrameTiming {
+    // CPU cycles at which the 4 step sequence raises the IRQ, then wraps
+    irq_cycles      : [u32; 3],
+    length_4_step   : u32,
+    length_5_step   : u32,
+}
+
+// Dendy clones use the NTSC tables
+const NTSC_FRAME : FrameTiming = FrameTiming {
+    irq_cycles      : [29828, 29829, 29830],
+    length_4_step   : 29830,
+    length_5_step   : 37282,
+};
+
+const PAL_FRAME : FrameTiming = FrameTiming {
+    irq_cycles      : [33252, 33253, 33254],
+    length_4_step   : 33254,
+    length_5_step   : 41566,
+};
+
+// DMC timer periods in CPU cycles
+const NTSC_DMC_RATES : [u16; 0x10] = [
+    428, 380, 340, 320, 286, 254, 226, 214,
+    190, 160, 142, 128, 106,  84,  72,  54,
+];
+
+const PAL_DMC_RATES : [u16; 0x10] = [
+    398, 354, 316, 298, 276, 236, 210, 198,
+    176, 148, 132, 118,  98,  78,  66,  50,
+];
+
+pub struct Apu {
+    frame           : FrameCounter,
+    dmc             : Dmc,
+    // CPU cycles since power up, writes to $4017 depend on its parity
+    cycles          : u64,
+    region          : Region,
+    // Output for the frontend, taken and cleared once per frame
+    samples         : Vec<i16>,
+    // CPU cycles times SAMPLE_RATE since the last sample
+    sample_debt     : u32,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            frame           : FrameCounter::default(),
+            dmc             : Dmc::default(),
+            cycles          : 0,
+            region          : Region::Ntsc,
+            samples         : Vec::new(),
+            sample_debt     : 0,
+        }
+    }
+
+    pub fn power_on(&mut self) {
+        let region = self.region;
+        *self = Apu::new();
+        self.region = region;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn samples(&mut self) -> &mut Vec<i16> {
+        &mut self.samples
+    }
+
+    // The frame counter restarts in the same mode and the DMC is silenced
+    pub fn soft_reset(&mut self) {
+        let control = self.frame.control;
+        self.frame.irq = false;
+        self.frame.set_control(control, self.cycles);
+        self.dmc.set_enabled(false);
+    }
+
+    // Runs a single CPU cycle
+    pub fn cycle(&mut self, memory: &mut Mem) {
+        self.ls_latches(memory);
+        let (frame, dmc_rates) = match self.region {
+            Region::Pal => (&PAL_FRAME, &PAL_DMC_RATES),
+            Region::Ntsc | Region::Dendy => (&NTSC_FRAME, &NTSC_DMC_RATES),
+        };
+        self.frame.cycle(frame);
+        if let Some(address) = self.dmc.cycle(dmc_rates) {
+            memory.log_prg(address, PRG_PCM);
+        }
+        self.cycles += 1;
+        self.sample_debt += SAMPLE_RATE;
+        if self.sample_debt >= self.region.cpu_clock() {
+            self.sample_debt -= self.region.cpu_clock();
+            if self.samples.len() < MAX_SAMPLES {
+                // Nothing to mix yet
+                self.samples.push(0);
+            }
+        }
+        memory.set_irq(IrqSource::FrameCounter, self.frame.irq);
+        memory.set_irq(IrqSource::Dmc, self.dmc.irq);
+        memory.set_apu_status(self.status());
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.dmc.bytes_left > 0 {
+            status |= STATUS_DMC_ACTIVE;
+        }
+        if self.frame.irq {
+            status |= STATUS_FRAME_IRQ;
+        }
+        if self.dmc.irq {
+            status |= STATUS_DMC_IRQ;
+        }
+        status
+    }
+
+    /* load store latches */
+    fn ls_latches(&mut self, memory: &mut Mem) {
+        if let Some((address, value)) = memory.get_apu_store() {
+            let value = value.0;
+            match address.0 {
+                0x4010 => self.dmc.set_control(value),
+                0x4012 => self.dmc.sample_address = value,
+                0x4013 => self.dmc.sample_length = value,
+                0x4015 => self.dmc.set_enabled(value & STATUS_DMC_ACTIVE != 0),
+                0x4017 => self.frame.set_control(value, self.cycles),
+                _      => (),
+            }
+        }
+        // Reading the status acknowledges the frame interrupt
+        if memory.apu_status_read() {
+            self.frame.irq = false;
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default () -> Apu {
+        Apu::new()
+    }
+}
+
+impl fmt::Debug for Apu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "APU: \n frame: {:?}, dmc: {:?}", self.frame, self.dmc)
+    }
+}
+
+#[derive(Default, Debug)]
+struct FrameCounter {
+    cycle           : u32,
+    control         : u8,
+    // A $4017 write takes effect 3 or 4 cycles later
+    pending         : Option<(u8, u8)>,
+    irq             : bool,
+}
+
+impl FrameCounter {
+
+    fn set_control(&mut self, value: u8, cpu_cycles: u64) {
+        if is_flag_set!(value, FRAME_IRQ_INHIBIT) {
+            self.irq = false;
+        }
+        let delay = if cpu_cycles & 1 == 0 {3} else {4};
+        self.pending = Some((value, delay));
+    }
+
+    fn cycle(&mut self, timing: &FrameTiming) {
+        if let Some((value, delay)) = self.pending {
+            if delay == 0 {
+                self.control = value;
+                self.cycle = 0;
+                self.pending = None;
+            } else {
+                self.pending = Some((value, delay - 1));
+            }
+        }
+        self.cycle += 1;
+        if is_flag_set!(self.control, FRAME_MODE_5_STEP) {
+            if self.cycle == timing.length_5_step {
+                self.cycle = 0;
+            }
+        } else {
+            if timing.irq_cycles.contains(&self.cycle) &&
+               !is_flag_set!(self.control, FRAME_IRQ_INHIBIT) {
+                self.irq = true;
+            }
+            if self.cycle == timing.length_4_step {
+                self.cycle = 0;
+            }
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct Dmc {
+    control         : u8,
+    sample_address  : u8,
+    sample_length   : u8,
+    timer           : u16,
+    // Bits left in the output shift register
+    bits_left       : u8,
+    buffer_full     : bool,
+    address         : W<u16>,
+    bytes_left      : u16,
+    irq             : bool,
+}
+
+impl Dmc {
+
+    fn set_control(&mut self, value: u8) {
+        self.control = value;
+        if !is_flag_set!(value, DMC_IRQ_ENABLE) {
+            self.irq = false;
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        // Writing to $4015 always acknowledges the DMC interrupt
+        self.irq = false;
+        if !enabled {
+            self.bytes_left = 0;
+        } else if self.bytes_left == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = W(0xC000) | W(self.sample_address as u16) << 6;
+        self.bytes_left = ((self.sample_length as u16) << 4) + 1;
+    }
+
+    // Returns the address of the sample byte read, if any
+    fn cycle(&mut self, rates: &[u16; 0x10]) -> Option<W<u16>> {
+        if self.timer > 0 {
+            self.timer -= 1;
+        } else {
+            self.timer = rates[(self.control & 0xF) as usize] - 1;
+            if self.bits_left > 0 {
+                self.bits_left -= 1;
+            } else {
+                // A new output cycle empties the sample buffer
+                self.bits_left = 7;
+                self.buffer_full = false;
+            }
+        }
+        if !self.buffer_full && self.bytes_left > 0 {
+            // The sample byte itself isn't fetched until there's audio
+            let address = self.address;
+            self.buffer_full = true;
+            self.address = (self.address + W(1)) | W(0x8000);
+            self.bytes_left -= 1;
+            if self.bytes_left == 0 {
+                if is_flag_set!(self.control, DMC_LOOP) {
+                    self.restart();
+                } else if is_flag_set!(self.control, DMC_IRQ_ENABLE) {
+                    self.irq = true;
+                }
+            }
+            Some(address)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// nes
+use nes::Nes;
+use ppu::Scanline;
+
+// Debug toggles a frontend can ask for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    Background,
+    Sprites,
+    PreviousSprite,
+    NextSprite,
+    HideSprite,
+    ShowAll,
+    // Palette of the pattern table viewer
+    ViewerPalette,
+    // Next upscaling filter
+    Scaler,
+}
+
+pub trait Video {
+    // Called before running, to set up what the output needs from the NES
+    fn start(&mut self, _nes: &mut Nes) {}
+
+    // A complete frame, number is the frame it was drawn in and phase the
+    // one of the color subcarrier at its start
+    fn frame(&mut self, frame: &[Scanline], number: u64, phase: u8)
+             -> Result<(), String>;
+
+    // Called after every CPU cycle, for views of the running state
+    fn cycle(&mut self, _nes: &Nes) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Mono samples at apu::SAMPLE_RATE, given once per frame
+pub trait Audio {
+    fn queue(&mut self, samples: &[i16]) -> Result<(), String>;
+}
+
+pub trait Input {
+    // Sets the controller keys and adds the hotkeys pressed since the last
+    // poll, returns true if the user wants to exit
+    fn poll(&mut self, keys: &mut [[u8; 8]; 2], hotkeys: &mut Vec<Hotkey>)
+            -> bool;
+}
+
+pub trait Backend : Video + Audio + Input {}
+
+impl<T: Video + Audio + Input> Backend for T {}
+
+// No output and no keys pressed, for tools and test harnesses
+pub struct Headless;
+
+impl Video for Headless {
+    fn frame(&mut self, _frame: &[Scanline], _number: u64, _phase: u8)
+             -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Audio for Headless {
+    fn queue(&mut self, _samples: &[i16]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Input for Headless {
+    fn poll(&mut self, _keys: &mut [[u8; 8]; 2], _hotkeys: &mut Vec<Hotkey>)
+            -> bool {
+        false
+    }
+}
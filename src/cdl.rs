@@ -0,0 +1,66 @@
+// std
+use std::fs::File;
+use std::io::prelude::*;
+use std::num::Wrapping as W;
+use std::path::Path;
+
+// FCEUX code/data log flags, PRG bytes are xPdcAADC and CHR bytes xxxxxxRD
+pub const PRG_CODE          : u8 = 0x01;
+pub const PRG_DATA          : u8 = 0x02;
+const PRG_BANK_MASK         : u8 = 0x0C;
+pub const PRG_INDIRECT_CODE : u8 = 0x10;
+pub const PRG_INDIRECT_DATA : u8 = 0x20;
+pub const PRG_PCM           : u8 = 0x40;
+pub const CHR_RENDERED      : u8 = 0x01;
+pub const CHR_READ          : u8 = 0x02;
+
+// Marks keyed by the offset in PRG and CHR ROM, as the .cdl file stores them
+pub struct CodeDataLog {
+    prg             : Box<[u8]>,
+    chr             : Box<[u8]>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_size: usize, chr_size: usize) -> CodeDataLog {
+        CodeDataLog {
+            prg             : vec![0; prg_size].into_boxed_slice(),
+            chr             : vec![0; chr_size].into_boxed_slice(),
+        }
+    }
+
+    // Continues a log saved for the same ROM
+    pub fn load<P: AsRef<Path>>(path: P, prg_size: usize, chr_size: usize)
+                               -> Result<CodeDataLog, String> {
+        let mut file = try_err!(File::open(path), "Couldn't open CDL file");
+        let mut data = Vec::new();
+        try_err!(file.read_to_end(&mut data), "Couldn't read CDL file");
+        if data.len() != prg_size + chr_size {
+            return err!("CDL file size doesn't match the ROM");
+        }
+        let chr = data.split_off(prg_size);
+        Ok(
+            CodeDataLog {
+                prg             : data.into_boxed_slice(),
+                chr             : chr.into_boxed_slice(),
+            }
+        )
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let mut file = try_err!(File::create(path), "Couldn't create CDL file");
+        try_err!(file.write_all(&self.prg), "Couldn't write CDL file");
+        try_err!(file.write_all(&self.chr), "Couldn't write CDL file");
+        Ok(())
+    }
+
+    // The bank bits keep the 8KiB window the byte was last accessed from
+    pub fn mark_prg(&mut self, offset: usize, address: W<u16>, flags: u8) {
+        let bank = ((address.0 >> 13) & 0x3) as u8;
+        let marks = &mut self.prg[offset];
+        *marks = *marks & !PRG_BANK_MASK | bank << 2 | flags;
+    }
+
+    pub fn mark_chr(&mut self, offset: usize, flags: u8) {
+        self.chr[offset] |= flags;
+    }
+}
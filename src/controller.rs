@@ -1,5 +1,3 @@
-extern crate sdl2;
-
 // NES
 use mem::Memory;
 use enums::IoState;
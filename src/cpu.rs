@@ -2,13 +2,9 @@ use std::fmt;
 use mem::Memory as Mem;
 use enums::Interrupt;
 use loadstore::LoadStore;
+use cdl::{PRG_CODE, PRG_DATA, PRG_INDIRECT_CODE, PRG_INDIRECT_DATA};
 use std::num::Wrapping as W;
 
-// Branch flag types
-const BRANCH_FLAG_CHECK : u8 = 0x20;
-const BRANCH_FLAG_TABLE : [W<u8>; 4] =
-    [FLAG_SIGN, FLAG_OVERFLOW, FLAG_CARRY, FLAG_ZERO];
-
 // Memory
 const STACK_PAGE        : W<u16> = W(0x0100 as u16);
 const PAGE_MASK         : W<u16> = W(0xFF00 as u16);
@@ -30,6 +26,11 @@ const FLAG_SIGN         : W<u8> = W(0x80);
 const OAMDATA           : W<u16> = W(0x2004);
 const DMA_CYCLES        : u32 = 512;
 
+const UNOFFICIAL        : [&'static str; 19] = [
+    "ahx", "alr", "anc", "arr", "axs", "dcp", "isc", "jam", "las", "lax",
+    "rla", "rra", "sax", "shx", "shy", "slo", "sre", "tas", "xaa",
+];
+
 #[derive(Default, Debug)]
 pub struct Cpu {
     // Cycle count since power up
@@ -39,23 +40,46 @@ pub struct Cpu {
     regs        : Regs,
     exec        : Execution,
     dma         : DMA,
+    // The last cycle fetched an opcode, for tracing
+    fetched     : bool,
+    // The last cycle belonged to an OAM DMA
+    in_dma      : bool,
 }
 
 impl Cpu {
-    pub fn reset(&mut self, memory: &mut Mem) {
+    // Registers are cleared, the reset sequence leaves SP at $FD
+    pub fn power_on(&mut self) {
+        *self = Cpu::default();
+        self.exec.interrupt = Some(Interrupt::Reset);
+    }
+
+    // Registers are kept, the reset sequence decrements SP by 3
+    pub fn soft_reset(&mut self) {
         self.exec = Execution::default();
         self.dma = DMA::default();
-        self.regs.reset(memory);
-        self.exec.load_operation(memory, &mut self.regs);
+        self.exec.interrupt = Some(Interrupt::Reset);
     }
 
     pub fn cycle(&mut self, memory: &mut Mem) {
+        let fetch = self.exec.opcode_next();
         // Dma takes priority
-        self.insts += (!self.dma.cycle(memory, self.cycles) &&
-                       self.exec.cycle(memory, &mut self.regs)) as u64;
+        let dma = self.dma.cycle(memory, self.cycles);
+        self.fetched = fetch && !dma;
+        self.in_dma = dma;
+        self.insts += (!dma && self.exec.cycle(memory, &mut self.regs)) as u64;
         self.cycles += 1;
     }
 
+    // Registers and PC are still the ones before the fetched instruction
+    // runs, only PC was incremented
+    pub fn fetched_opcode(&self) -> bool {
+        self.fetched
+    }
+
+    pub fn in_dma(&self) -> bool {
+        self.in_dma
+    }
+
     pub fn instruction_count(&self) -> u64 {
         self.insts
     }
@@ -71,6 +95,10 @@ impl Cpu {
     pub fn cycle_count(&self) -> u64 {
         self.cycles
     }
+
+    pub fn jammed(&self) -> bool {
+        self.exec.jammed()
+    }
 }
 
 #[derive(Default)]
@@ -114,51 +142,286 @@ impl DMA {
     }
 }
 
+// How an instruction uses the bus once its effective address is known
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    // Loads the operand and operates on it
+    Read,
+    // Operates and stores the result
+    Write,
+    // Loads the operand, writes it back unmodified and then the result
+    Modify,
+    // Unstable stores, the value is ANDed with the base address high byte
+    // plus one and replaces that high byte when a page is crossed
+    WriteHigh,
+    // Only operates on registers, or is fully driven by its addressing
+    Implied,
+}
+
+// Every call to cycle performs the single bus access the 6502 does on that
+// cycle, dummy reads and writes included, since they have side effects on
+// the PPU, IO and mapper registers.
 #[derive(Default, Debug)]
 pub struct Execution {
+    // Address of the current opcode
+    pub pc              : W<u16>,
+    // Effective address, or branch target
     pub address         : W<u16>,
-    pub cycles_left     : u32,
     pub operation       : Operation,
+    // Cycle of the current instruction, 0 is the opcode fetch
+    step                : u32,
+    // Zero page pointer for indirect modes
+    pointer             : W<u8>,
+    // Data latched between cycles of read-modify-write instructions
+    value               : W<u8>,
+    // The indexed address crossed a page and needs an extra cycle
+    crossed             : bool,
+    // Interrupt polled at the end of the previous instruction
+    interrupt           : Option<Interrupt>,
+    // Lines as seen at the end of the previous cycle
+    poll                : Option<Interrupt>,
+    // Taken branches without page cross don't poll on their last cycle
+    skip_poll           : bool,
+    // NMI edge detected and not serviced yet
+    nmi                 : bool,
+    // Halted by a JAM opcode until reset
+    jammed              : bool,
 }
 
 impl Execution {
 
-    pub fn load_operation(&mut self, memory: &mut Mem, regs: &mut Regs) {
-        self.operation = if let Some(interrupt) = memory.get_interrupt() {
-            if interrupt == Interrupt::IRQ && regs.no_irq() {
-                Operation::from_address(memory, regs.PC)
+    // Returns true if an instruction was completed in this cycle
+    pub fn cycle(&mut self, memory: &mut Mem, regs: &mut Regs) -> bool {
+        if self.jammed {
+            memory.load(W(0xFFFF));
+            return false;
+        }
+        if !self.skip_poll {
+            self.poll_interrupt(memory, regs);
+        }
+        self.skip_poll = false;
+        if self.step == 0 {
+            self.load_operation(memory, regs);
+            self.step = 1;
+            return false;
+        }
+        let done = (self.operation.inst.mode.function)(self, regs, memory);
+        if done {
+            debug_assert!(self.step + 1 == self.expected_cycles(),
+                          "{} took {} cycles", self.operation.inst.name,
+                          self.step + 1);
+            // What was polled before the last cycle decides
+            self.step = 0;
+            self.interrupt = self.poll;
+        } else {
+            self.step += 1;
+        }
+        done
+    }
+
+    fn load_operation(&mut self, memory: &mut Mem, regs: &mut Regs) {
+        self.pc = regs.PC;
+        let opcode = memory.load(regs.PC).0;
+        self.operation = if let Some(interrupt) = self.interrupt.take() {
+            // The opcode fetch is discarded and PC isn't incremented
+            Operation::from_interrupt(interrupt)
+        } else {
+            // Only JMP uses the indirect mode
+            let indirect = if self.operation.inst.mode.name == "ind" {
+                PRG_INDIRECT_CODE
             } else {
-                Operation::from_interrupt(memory, interrupt)
-            }
+                0
+            };
+            memory.log_prg(regs.PC, PRG_CODE | indirect);
+            regs.PC += W(1);
+            Operation::from_opcode(opcode)
+        };
+    }
+
+    // The I flag is the one before the current cycle runs, so CLI, SEI and
+    // PLP affect IRQs only after the next instruction
+    fn poll_interrupt(&mut self, memory: &mut Mem, regs: &Regs) {
+        if memory.get_nmi() {
+            self.nmi = true;
+        }
+        // A pending NMI doesn't drop the IRQ, it stays until acknowledged
+        self.poll = if self.nmi {
+            Some(Interrupt::NMI)
+        } else if memory.irq() && !regs.no_irq() {
+            Some(Interrupt::IRQ)
         } else {
-            Operation::from_address(memory, regs.PC)
+            None
         };
-        // Get address and extra cycles from mode
-        let operand = self.operation.operand;
-        let mode = &self.operation.inst.mode;
-        let (address, extra) = (mode.function)(regs, memory, operand);
-        // Get next operation
-        self.address = address;
-        self.cycles_left = self.operation.inst.cycles - 1;
-        // Add the extra cycles if needed
-        if self.operation.inst.has_extra {
-            self.cycles_left += extra;
+    }
+
+    // Cycles of the instruction table plus the page crossing and taken
+    // branch ones
+    fn expected_cycles(&self) -> u32 {
+        let inst = self.operation.inst;
+        let crossed = self.crossed as u32;
+        match (inst.mode.name, inst.access) {
+            // Branches not taken end on the operand fetch
+            ("rel", _) if self.step == 1 => inst.cycles,
+            ("rel", _) => inst.cycles + 1 + crossed,
+            ("abx", Access::Read) | ("aby", Access::Read) |
+            ("idy", Access::Read) => inst.cycles + crossed,
+            _ => inst.cycles,
         }
     }
 
-    pub fn cycle(&mut self, memory: &mut Mem, regs: &mut Regs) -> bool {
-        if self.cycles_left == 0 {
-            // Advance the PC
-            regs.PC += self.operation.inst.mode.size;
-            // Execute the instruction
-            (self.operation.inst.function)(regs, memory, self.address);
-            self.load_operation(memory, regs);
+    pub fn jammed(&self) -> bool {
+        self.jammed
+    }
+
+    // The next cycle fetches an opcode that will run
+    fn opcode_next(&self) -> bool {
+        self.step == 0 && self.interrupt.is_none() && !self.jammed
+    }
+
+    fn operate(&self, regs: &mut Regs, value: W<u8>) -> W<u8> {
+        (self.operation.inst.function)(regs, value)
+    }
+
+    // Reads the byte at PC and advances it
+    fn fetch(&mut self, regs: &mut Regs, memory: &mut Mem) -> W<u8> {
+        memory.log_prg(regs.PC, PRG_CODE);
+        let value = memory.load(regs.PC);
+        regs.PC += W(1);
+        value
+    }
+
+    fn fetch_low(&mut self, regs: &mut Regs, memory: &mut Mem) {
+        self.operation.operand = W16!(self.fetch(regs, memory));
+    }
+
+    fn fetch_high(&mut self, regs: &mut Regs, memory: &mut Mem) {
+        let high = self.fetch(regs, memory);
+        set_high_byte!(self.operation.operand, high);
+    }
+
+    fn dummy_stack_read(&mut self, regs: &mut Regs, memory: &mut Mem) {
+        memory.load(STACK_PAGE | W16!(regs.SP));
+    }
+
+    // Data reads through a pointer in zero page are indirect
+    fn load_data(&self, memory: &mut Mem, address: W<u16>) -> W<u8> {
+        let indirect = match self.operation.inst.mode.name {
+            "idx" | "idy" => PRG_INDIRECT_DATA,
+            _ => 0,
+        };
+        memory.log_prg(address, PRG_DATA | indirect);
+        memory.load(address)
+    }
+
+    fn index(&mut self, base: W<u16>, index: W<u8>) {
+        self.address = base + W16!(index);
+        self.crossed = (self.address & PAGE_MASK) != (base & PAGE_MASK);
+    }
+
+    // Reads from the indexed address before the page carry is added. When no
+    // page was crossed this is already the real read of a read instruction.
+    fn fix_page(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let partial = if self.crossed {
+            self.address - W(0x100)
+        } else {
+            self.address
+        };
+        if !self.crossed && self.operation.inst.access == Access::Read {
+            let value = self.load_data(memory, partial);
+            self.operate(regs, value);
             true
         } else {
-            self.cycles_left -= 1;
+            memory.load(partial);
             false
         }
     }
+
+    // Cycles after the effective address is known, step starts at 0
+    fn access(&mut self, regs: &mut Regs, memory: &mut Mem, step: u32) -> bool {
+        let address = self.address;
+        match (self.operation.inst.access, step) {
+            (Access::Read, 0) => {
+                let value = self.load_data(memory, address);
+                self.operate(regs, value);
+                true
+            },
+            (Access::Write, 0) => {
+                let value = self.operate(regs, W(0));
+                memory.store(address, value);
+                true
+            },
+            (Access::Modify, 0) => {
+                self.value = self.load_data(memory, address);
+                false
+            },
+            (Access::Modify, 1) => {
+                // The unmodified value is written back first
+                memory.store(address, self.value);
+                false
+            },
+            (Access::Modify, 2) => {
+                let value = self.value;
+                let result = self.operate(regs, value);
+                memory.store(address, result);
+                true
+            },
+            (Access::WriteHigh, 0) => {
+                let mut base_high = W8!(address >> 8);
+                if self.crossed {
+                    base_high -= W(1);
+                }
+                let value = self.operate(regs, base_high + W(1));
+                if self.crossed {
+                    let mut corrupted = address;
+                    set_high_byte!(corrupted, value);
+                    memory.store(corrupted, value);
+                } else {
+                    memory.store(address, value);
+                }
+                true
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Pushes PC and flags, then jumps through the vector at self.address.
+    // An NMI detected before the flags are pushed hijacks BRK and IRQ.
+    fn push_interrupt(&mut self, regs: &mut Regs, memory: &mut Mem,
+                      step: u32, flags: W<u8>) -> bool {
+        match step {
+            2 => {
+                let high = W8!(regs.PC >> 8);
+                regs.push(memory, high);
+            },
+            3 => {
+                let low = W8!(regs.PC);
+                regs.push(memory, low);
+            },
+            4 => {
+                let flags = regs.P | flags;
+                regs.push(memory, flags);
+                set_flag!(regs.P, FLAG_INTERRUPT);
+                if self.nmi {
+                    self.nmi = false;
+                    self.address = ADDRESS_NMI;
+                }
+            },
+            5 => {
+                memory.log_prg(self.address, PRG_DATA);
+                let low = memory.load(self.address);
+                set_low_byte!(regs.PC, low);
+            },
+            _ => {
+                memory.log_prg(self.address + W(1), PRG_DATA);
+                let high = memory.load(self.address + W(1));
+                set_high_byte!(regs.PC, high);
+                // The first handler instruction always runs
+                self.poll = None;
+                return true;
+            },
+        }
+        false
+    }
 }
 
 pub struct Operation {
@@ -178,29 +441,42 @@ impl Default for Operation {
 }
 
 impl Operation {
-    pub fn from_address(memory: &mut Mem, address: W<u16>) -> Operation {
-        let opcode = memory.load(address).0;
-        let inst = &OPCODE_TABLE[opcode as usize];
-        let operand : W<u16> = match inst.mode.size {
-            W(1) => W(0),
-            W(2) => W16!(memory.load(address + W(1))),
-            W(3) => memory.load_word(address + W(1)),
-            _    => unreachable!(),
-        };
+    pub fn from_opcode(opcode: u8) -> Operation {
         Operation {
-            inst    : inst,
+            inst    : &OPCODE_TABLE[opcode as usize],
             opcode  : opcode,
-            operand : operand,
+            operand : W(0),
+        }
+    }
+
+    // Decodes the operation at address without side effects
+    pub fn from_address(memory: &Mem, address: W<u16>) -> Operation {
+        let opcode = memory.peek(address).0;
+        let mut operation = Operation::from_opcode(opcode);
+        let size = operation.inst.mode.size.0;
+        if size > 1 {
+            operation.operand = W16!(memory.peek(address + W(1)));
+        }
+        if size > 2 {
+            operation.operand |= W16!(memory.peek(address + W(2))) << 8;
         }
+        operation
     }
 
-    pub fn from_interrupt(_: &mut Mem, interrupt: Interrupt) -> Operation {
-        let address = match interrupt {
-            Interrupt::NMI => ADDRESS_NMI,
-            Interrupt::IRQ => ADDRESS_IRQ,
+    // Unofficial opcodes and duplicates of official ones
+    pub fn is_official(&self) -> bool {
+        !UNOFFICIAL.contains(&self.inst.name) &&
+        (self.inst.name != "nop" || self.opcode == 0xEA) && self.opcode != 0xEB
+    }
+
+    pub fn from_interrupt(interrupt: Interrupt) -> Operation {
+        let (inst, address) = match interrupt {
+            Interrupt::NMI => (INT, ADDRESS_NMI),
+            Interrupt::IRQ => (INT, ADDRESS_IRQ),
+            Interrupt::Reset => (RST, ADDRESS_RESET),
         };
         Operation {
-            inst : INT,
+            inst : inst,
             opcode : 0,
             operand : address,
         }
@@ -216,14 +492,15 @@ impl fmt::Debug for Operation {
 
 pub struct Instruction {
     pub mode        : Addressing,
-    pub function    : fn(&mut Regs, &mut Mem, W<u16>),
+    pub access      : Access,
+    pub function    : fn(&mut Regs, W<u8>) -> W<u8>,
     pub cycles      : u32,
-    pub has_extra   : bool,
     pub name        : &'static str,
 }
 
 pub struct Addressing {
-    pub function    : fn(&mut Regs, &mut Mem, W<u16>) -> (W<u16>, u32),
+    // Runs one cycle of the instruction, returns true on the last one
+    pub function    : fn(&mut Execution, &mut Regs, &mut Mem) -> bool,
     pub size        : W<u16>,
     pub name        : &'static str,
 }
@@ -243,21 +520,6 @@ pub struct Regs {
 
 impl Regs {
 
-    pub fn reset(&mut self, memory: &mut Mem) {
-        set_flag!(self.P, FLAG_INTERRUPT);
-        self.SP -= W(3);
-        self.PC = memory.load_word(ADDRESS_RESET);
-    }
-
-    pub fn int(&mut self, memory: &mut Mem, address: W<u16>) {
-        let pc = self.PC;
-        self.push_word(memory, pc);
-        // FLAG_BRK not set on interrupt
-        let flags = self.P | FLAG_PUSHED;
-        self.push(memory, flags);
-        self.PC = memory.load_word(address);
-    }
-
     pub fn no_irq(&self) -> bool {
         self.P & FLAG_INTERRUPT > W(0)
     }
@@ -272,25 +534,14 @@ impl Regs {
         self.SP = self.SP - W(1);
     }
 
-    fn push_word(&mut self, memory: &mut Mem, word: W<u16>) {
-        self.push(memory, W8!(word >> 8));
-        self.push(memory, W8!(word));
-    }
-
-    fn pop_word(&mut self, memory: &mut Mem) -> W<u16> {
-        let low = W16!(self.pop(memory));
-        (W16!(self.pop(memory)) << 8) | low
-    }
-
-    fn push_flags(&mut self, memory: &mut Mem) {
-        // Two bits are set on memory when pushing flags with instructions
-        let flags = self.P | FLAG_PUSHED | FLAG_BRK;
-        self.push(memory, flags);
+    fn set_flags(&mut self, flags: W<u8>) {
+        // Ignore the two bits not present
+        self.P = flags & !(FLAG_PUSHED | FLAG_BRK);
     }
 
-    fn pop_flags(&mut self, memory: &mut Mem) {
-        // Ignore the two bits not present
-        self.P = self.pop(memory) & !(FLAG_PUSHED | FLAG_BRK);
+    // Branches return 1 when taken
+    fn branch_if(&self, flag: W<u8>, set: bool) -> W<u8> {
+        W((is_bit_set!(self.P, flag) == set) as u8)
     }
 
     fn add_with_carry(&mut self, value: W<u8>) {
@@ -336,420 +587,756 @@ impl Regs {
     }
 }
 
-// Addressing modes
+// Addressing modes, step 0 was the opcode fetch
 
-impl Regs {
+impl Execution {
 
-    fn imp(&mut self, _: &mut Mem, _: W<u16>) -> (W<u16>, u32) {
-        (W(0), 0)
+    fn imp(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        // Dummy read of the next byte
+        memory.load(regs.PC);
+        self.operate(regs, W(0));
+        true
+    }
+
+    fn imm(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        self.address = regs.PC;
+        let value = self.fetch(regs, memory);
+        self.operation.operand = W16!(value);
+        self.operate(regs, value);
+        true
+    }
+
+    fn ind(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let pointer = self.operation.operand;
+        match self.step {
+            1 => self.fetch_low(regs, memory),
+            2 => self.fetch_high(regs, memory),
+            3 => self.address = W16!(self.load_data(memory, pointer)),
+            _ => {
+                // The pointer high byte is read without page carry
+                let next = (pointer & PAGE_MASK) | W16!(W8!(pointer) + W(1));
+                let high = self.load_data(memory, next);
+                set_high_byte!(self.address, high);
+                regs.PC = self.address;
+                return true;
+            },
+        }
+        false
+    }
+
+    fn idx(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                self.fetch_low(regs, memory);
+                self.pointer = W8!(self.operation.operand);
+            },
+            2 => {
+                memory.load(W16!(self.pointer));
+                self.pointer += regs.X;
+            },
+            3 => self.address = W16!(memory.load(W16!(self.pointer))),
+            4 => {
+                let high = memory.load(W16!(self.pointer + W(1)));
+                set_high_byte!(self.address, high);
+            },
+            step => return self.access(regs, memory, step - 5),
+        }
+        false
+    }
+
+    fn idy(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                self.fetch_low(regs, memory);
+                self.pointer = W8!(self.operation.operand);
+            },
+            2 => self.address = W16!(memory.load(W16!(self.pointer))),
+            3 => {
+                let high = memory.load(W16!(self.pointer + W(1)));
+                let mut base = self.address;
+                set_high_byte!(base, high);
+                self.index(base, regs.Y);
+            },
+            4 => return self.fix_page(regs, memory),
+            step => return self.access(regs, memory, step - 5),
+        }
+        false
     }
 
-    fn imm(&mut self, _: &mut Mem, _: W<u16>) -> (W<u16>, u32) {
-        (self.PC + W(1), 0)
+    fn zpg(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                self.fetch_low(regs, memory);
+                self.address = self.operation.operand;
+            },
+            step => return self.access(regs, memory, step - 2),
+        }
+        false
     }
 
-    fn ind(&mut self, memory: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (memory.load_word_page_wrap(operand), 0)
+    fn zpx(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let x = regs.X;
+        self.zero_page_indexed(regs, memory, x)
     }
 
-    fn idx(&mut self, memory: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (memory.load_word_page_wrap(W16!(W8!(operand) + self.X)), 0)
+    fn zpy(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let y = regs.Y;
+        self.zero_page_indexed(regs, memory, y)
     }
 
-    fn idy(&mut self, memory: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        let dest = memory.load_word_page_wrap(operand) + W16!(self.Y);
-        (dest, (W8!(dest) < self.Y) as u32)
+    fn zero_page_indexed(&mut self, regs: &mut Regs, memory: &mut Mem,
+                         index: W<u8>) -> bool {
+        match self.step {
+            1 => self.fetch_low(regs, memory),
+            2 => {
+                let operand = self.operation.operand;
+                memory.load(operand);
+                self.address = W16!(W8!(operand) + index);
+            },
+            step => return self.access(regs, memory, step - 3),
+        }
+        false
     }
 
-    fn zpg(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (operand, 0)
+    fn abs(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => self.fetch_low(regs, memory),
+            2 => {
+                self.fetch_high(regs, memory);
+                self.address = self.operation.operand;
+            },
+            step => return self.access(regs, memory, step - 3),
+        }
+        false
+    }
+
+    fn abx(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let x = regs.X;
+        self.absolute_indexed(regs, memory, x)
     }
 
-    fn zpx(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (W16!(W8!(operand) + self.X), 0)
+    fn aby(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        let y = regs.Y;
+        self.absolute_indexed(regs, memory, y)
     }
 
-    fn zpy(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (W16!(W8!(operand) + self.Y), 0)
+    fn absolute_indexed(&mut self, regs: &mut Regs, memory: &mut Mem,
+                        index: W<u8>) -> bool {
+        match self.step {
+            1 => self.fetch_low(regs, memory),
+            2 => {
+                self.fetch_high(regs, memory);
+                let base = self.operation.operand;
+                self.index(base, index);
+            },
+            3 => return self.fix_page(regs, memory),
+            step => return self.access(regs, memory, step - 4),
+        }
+        false
+    }
+
+    fn rel(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                let offset = self.fetch(regs, memory);
+                self.operation.operand = W16!(offset);
+                self.address = regs.PC + W(offset.0 as i8 as u16);
+                self.crossed = (self.address & PAGE_MASK) !=
+                               (regs.PC & PAGE_MASK);
+                let taken = self.operate(regs, W(0)) != W(0);
+                // Only the page fixup cycle polls again
+                self.skip_poll = taken && !self.crossed;
+                return !taken;
+            },
+            2 => {
+                memory.load(regs.PC);
+                let target = self.address;
+                set_low_byte!(regs.PC, target);
+                // Additional cycle if branch taken and page boundary crossed
+                return !self.crossed;
+            },
+            _ => {
+                memory.load(regs.PC);
+                regs.PC = self.address;
+            },
+        }
+        true
+    }
+
+    // Modes that drive the whole instruction
+
+    fn jmp(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                self.fetch_low(regs, memory);
+                false
+            },
+            _ => {
+                self.fetch_high(regs, memory);
+                self.address = self.operation.operand;
+                regs.PC = self.address;
+                true
+            },
+        }
     }
 
-    fn abs(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        (operand, 0)
+    fn jsr(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => self.fetch_low(regs, memory),
+            2 => self.dummy_stack_read(regs, memory),
+            // The return address pushed is the one of the high byte
+            3 => {
+                let high = W8!(regs.PC >> 8);
+                regs.push(memory, high);
+            },
+            4 => {
+                let low = W8!(regs.PC);
+                regs.push(memory, low);
+            },
+            _ => {
+                self.fetch_high(regs, memory);
+                self.address = self.operation.operand;
+                regs.PC = self.address;
+                return true;
+            },
+        }
+        false
+    }
+
+    fn rts(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => { memory.load(regs.PC); },
+            2 => self.dummy_stack_read(regs, memory),
+            3 => {
+                let low = regs.pop(memory);
+                set_low_byte!(regs.PC, low);
+            },
+            4 => {
+                let high = regs.pop(memory);
+                set_high_byte!(regs.PC, high);
+            },
+            _ => {
+                self.fetch(regs, memory);
+                return true;
+            },
+        }
+        false
+    }
+
+    fn rti(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => { memory.load(regs.PC); },
+            2 => self.dummy_stack_read(regs, memory),
+            3 => {
+                let flags = regs.pop(memory);
+                regs.set_flags(flags);
+            },
+            4 => {
+                let low = regs.pop(memory);
+                set_low_byte!(regs.PC, low);
+            },
+            _ => {
+                let high = regs.pop(memory);
+                set_high_byte!(regs.PC, high);
+                return true;
+            },
+        }
+        false
+    }
+
+    fn brk(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                // The padding byte is skipped
+                self.fetch(regs, memory);
+                self.address = ADDRESS_IRQ;
+                false
+            },
+            // Two bits are set on memory when pushing flags with instructions
+            step => self.push_interrupt(regs, memory, step,
+                                        FLAG_PUSHED | FLAG_BRK),
+        }
     }
 
-    fn abx(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        let address = operand + W16!(self.X);
-        (address, (W8!(address) < self.X) as u32)
+    fn int(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                memory.load(regs.PC);
+                self.address = self.operation.operand;
+                false
+            },
+            // FLAG_BRK not set on interrupt
+            step => self.push_interrupt(regs, memory, step, FLAG_PUSHED),
+        }
     }
 
-    fn aby(&mut self, _: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        let address = operand + W16!(self.Y);
-        (address, (W8!(address) < self.Y) as u32)
+    fn jam(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        // The CPU locks up with the bus reading $FFFF after this, only a
+        // reset recovers it
+        memory.load(regs.PC);
+        self.jammed = true;
+        false
+    }
+
+    fn rst(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                memory.load(regs.PC);
+                self.address = self.operation.operand;
+            },
+            // The pushes are done with writes disabled
+            2 | 3 | 4 => {
+                self.dummy_stack_read(regs, memory);
+                regs.SP -= W(1);
+                set_flag!(regs.P, FLAG_INTERRUPT);
+            },
+            step => return self.push_interrupt(regs, memory, step, W(0)),
+        }
+        false
+    }
+
+    fn psh(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => {
+                memory.load(regs.PC);
+                false
+            },
+            _ => {
+                let value = self.operate(regs, W(0));
+                regs.push(memory, value);
+                true
+            },
+        }
     }
 
-    fn rel(&mut self, memory: &mut Mem, operand: W<u16>) -> (W<u16>, u32) {
-        let opcode = memory.load(self.PC).0;
-        let index = (opcode >> 6) as usize;
-        let check = is_flag_set!(opcode, BRANCH_FLAG_CHECK);
-        let next_opcode = self.PC + W(2);
-        if is_bit_set!(self.P, BRANCH_FLAG_TABLE[index]) != check {
-            (next_opcode, 0)
-        } else {
-            // Branch taken
-            let branch = next_opcode + W(operand.0 as i8 as u16);
-            let crossed = (branch & PAGE_MASK) != (next_opcode & PAGE_MASK);
-            // Additional cycle if branch taken and page boundary crossed
-            (branch, 1 + crossed as u32)
+    fn pul(&mut self, regs: &mut Regs, memory: &mut Mem) -> bool {
+        match self.step {
+            1 => { memory.load(regs.PC); },
+            2 => self.dummy_stack_read(regs, memory),
+            _ => {
+                let value = regs.pop(memory);
+                self.operate(regs, value);
+                return true;
+            },
         }
+        false
     }
 }
 
-// Instructions
+// Instructions, they get the loaded operand and return the value to store
 
 impl Regs {
 
-    // Jump
+    // Branch
 
-    fn jsr(&mut self, memory: &mut Mem, address: W<u16>) {
-        // Load destination address and push return address
-        let ret = self.PC - W(1);
-        self.push_word(memory, ret);
-        self.PC = address;
+    fn bpl(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_SIGN, false)
     }
 
-    fn jmp(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bmi(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_SIGN, true)
     }
 
-    // Branch
-
-    fn bpl(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bvc(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_OVERFLOW, false)
     }
 
-    fn bmi(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bvs(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_OVERFLOW, true)
     }
 
-    fn bvc(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bcc(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_CARRY, false)
     }
 
-    fn bvs(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bcs(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_CARRY, true)
     }
 
-    fn bcc(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn bne(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_ZERO, false)
     }
 
-    fn bcs(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn beq(&mut self, _: W<u8>) -> W<u8> {
+        self.branch_if(FLAG_ZERO, true)
     }
 
-    fn bne(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
-    }
+    // Stack
 
-    fn beq(&mut self, _: &mut Mem, address: W<u16>) {
-        self.PC = address;
+    fn php(&mut self, _: W<u8>) -> W<u8> {
+        // Two bits are set on memory when pushing flags with instructions
+        self.P | FLAG_PUSHED | FLAG_BRK
     }
 
-    // Implied special
-
-    fn brk(&mut self, memory: &mut Mem, _: W<u16>) {
-       let pc = self.PC + W(1);
-       self.push_word(memory, pc);
-       self.push_flags(memory);
-       set_flag!(self.P, FLAG_INTERRUPT);
-       self.PC = memory.load_word(ADDRESS_IRQ);
+    fn plp(&mut self, value: W<u8>) -> W<u8> {
+        self.set_flags(value);
+        value
     }
 
-    fn rti(&mut self, memory: &mut Mem, _: W<u16>) {
-        self.pop_flags(memory);
-        self.PC = self.pop_word(memory);
+    fn pha(&mut self, _: W<u8>) -> W<u8> {
+        self.A
     }
 
-    fn rts(&mut self, memory: &mut Mem, _: W<u16>) {
-        self.PC = self.pop_word(memory) + W(1);
+    fn pla(&mut self, value: W<u8>) -> W<u8> {
+        self.A = value;
+        set_sign_zero!(self.P, self.A);
+        value
     }
 
     // Implied
 
-    fn php(&mut self, memory: &mut Mem, _: W<u16>) {
-        self.push_flags(memory);
-    }
-
-    fn sal(&mut self, _: &mut Mem, _: W<u16>) {
+    fn sal(&mut self, _: W<u8>) -> W<u8> {
         let a = self.A;
         self.A = self.shift_left(a);
+        self.A
     }
 
-    fn clc(&mut self, _: &mut Mem, _: W<u16>) {
+    fn clc(&mut self, value: W<u8>) -> W<u8> {
         unset_flag!(self.P, FLAG_CARRY);
+        value
     }
 
-    fn plp(&mut self, memory: &mut Mem, _: W<u16>) {
-        self.pop_flags(memory);
-    }
-
-    fn ral(&mut self, _: &mut Mem, _: W<u16>) {
+    fn ral(&mut self, _: W<u8>) -> W<u8> {
         let a = self.A;
         self.A = self.rotate_left(a);
+        self.A
     }
 
-    fn sec(&mut self, _: &mut Mem, _: W<u16>) {
+    fn sec(&mut self, value: W<u8>) -> W<u8> {
         set_flag!(self.P, FLAG_CARRY);
+        value
     }
 
-    fn pha(&mut self, memory: &mut Mem, _: W<u16>) {
-        let a = self.A;
-        self.push(memory, a);
-    }
-
-    fn sar(&mut self, _: &mut Mem, _: W<u16>) {
+    fn sar(&mut self, _: W<u8>) -> W<u8> {
         let a = self.A;
         self.A = self.shift_right(a);
+        self.A
     }
 
-    fn cli(&mut self, _: &mut Mem, _: W<u16>) {
+    fn cli(&mut self, value: W<u8>) -> W<u8> {
         unset_flag!(self.P, FLAG_INTERRUPT);
+        value
     }
 
-    fn pla(&mut self, memory: &mut Mem, _: W<u16>) {
-        self.A = self.pop(memory);
-        set_sign_zero!(self.P, self.A);
-    }
-
-    fn rar(&mut self, _: &mut Mem, _: W<u16>) {
+    fn rar(&mut self, _: W<u8>) -> W<u8> {
         let a = self.A;
         self.A = self.rotate_right(a);
+        self.A
     }
 
-    fn sei(&mut self, _: &mut Mem, _: W<u16>) {
+    fn sei(&mut self, value: W<u8>) -> W<u8> {
         set_flag!(self.P, FLAG_INTERRUPT);
+        value
     }
 
-    fn dey(&mut self, _: &mut Mem, _: W<u16>) {
+    fn dey(&mut self, _: W<u8>) -> W<u8> {
         self.Y -= W(1);
         set_sign_zero!(self.P, self.Y);
+        self.Y
     }
 
-    fn txa(&mut self, _: &mut Mem, _: W<u16>) {
+    fn txa(&mut self, _: W<u8>) -> W<u8> {
         self.A = self.X;
         set_sign_zero!(self.P, self.A);
+        self.A
     }
 
-    fn tya(&mut self, _: &mut Mem, _: W<u16>) {
+    fn tya(&mut self, _: W<u8>) -> W<u8> {
         self.A = self.Y;
         set_sign_zero!(self.P, self.A);
+        self.A
     }
 
-    fn txs(&mut self, _: &mut Mem, _: W<u16>) {
+    fn txs(&mut self, _: W<u8>) -> W<u8> {
         self.SP = self.X;
+        self.SP
     }
 
-    fn tay(&mut self, _: &mut Mem, _: W<u16>) {
+    fn tay(&mut self, _: W<u8>) -> W<u8> {
         self.Y = self.A;
         set_sign_zero!(self.P, self.Y);
+        self.Y
     }
 
-    fn tax(&mut self, _: &mut Mem, _: W<u16>) {
+    fn tax(&mut self, _: W<u8>) -> W<u8> {
         self.X = self.A;
         set_sign_zero!(self.P, self.X);
+        self.X
     }
 
-    fn clv(&mut self, _: &mut Mem, _: W<u16>) {
+    fn clv(&mut self, value: W<u8>) -> W<u8> {
         unset_flag!(self.P, FLAG_OVERFLOW);
+        value
     }
 
-    fn tsx(&mut self, _: &mut Mem, _: W<u16>) {
+    fn tsx(&mut self, _: W<u8>) -> W<u8> {
         self.X = self.SP;
         set_sign_zero!(self.P, self.X);
+        self.X
     }
 
-    fn iny(&mut self, _: &mut Mem, _: W<u16>) {
+    fn iny(&mut self, _: W<u8>) -> W<u8> {
         self.Y += W(1);
         set_sign_zero!(self.P, self.Y);
+        self.Y
     }
 
-    fn dex(&mut self, _: &mut Mem, _: W<u16>) {
+    fn dex(&mut self, _: W<u8>) -> W<u8> {
         self.X -= W(1);
         set_sign_zero!(self.P, self.X);
+        self.X
     }
 
-    fn cld(&mut self, _: &mut Mem, _: W<u16>) {
+    fn cld(&mut self, value: W<u8>) -> W<u8> {
         unset_flag!(self.P, FLAG_DECIMAL);
+        value
     }
 
-    fn inx(&mut self, _: &mut Mem, _: W<u16>) {
+    fn inx(&mut self, _: W<u8>) -> W<u8> {
         self.X += W(1);
         set_sign_zero!(self.P, self.X);
+        self.X
     }
 
-    fn nop(&mut self, _: &mut Mem, _: W<u16>) {}
+    fn nop(&mut self, value: W<u8>) -> W<u8> {
+        value
+    }
 
-    fn sed(&mut self, _: &mut Mem, _: W<u16>) {
+    fn sed(&mut self, value: W<u8>) -> W<u8> {
         set_flag!(self.P, FLAG_DECIMAL);
+        value
     }
 
     // Common
 
-    fn ora(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.A |= memory.load(address);
+    fn ora(&mut self, value: W<u8>) -> W<u8> {
+        self.A |= value;
         set_sign_zero!(self.P, self.A);
+        value
     }
 
-    fn asl(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = self.shift_left(memory.load(address));
-        memory.store(address, m);
+    fn asl(&mut self, value: W<u8>) -> W<u8> {
+        self.shift_left(value)
     }
 
-    fn bit(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address);
-        copy_bits!(self.P, m, FLAG_OVERFLOW);
-        set_sign!(self.P, m);
-        set_zero!(self.P, self.A & m);
+    fn bit(&mut self, value: W<u8>) -> W<u8> {
+        copy_bits!(self.P, value, FLAG_OVERFLOW);
+        set_sign!(self.P, value);
+        set_zero!(self.P, self.A & value);
+        value
     }
 
-    fn and(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.A &= memory.load(address);
+    fn and(&mut self, value: W<u8>) -> W<u8> {
+        self.A &= value;
         set_sign_zero!(self.P, self.A);
+        value
     }
 
-    fn rol(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = self.rotate_left(memory.load(address));
-        memory.store(address, m);
+    fn rol(&mut self, value: W<u8>) -> W<u8> {
+        self.rotate_left(value)
     }
 
-    fn eor(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.A ^= memory.load(address);
+    fn eor(&mut self, value: W<u8>) -> W<u8> {
+        self.A ^= value;
         set_sign_zero!(self.P, self.A);
+        value
     }
 
-    fn lsr(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = self.shift_right(memory.load(address));
-        memory.store(address, m);
+    fn lsr(&mut self, value: W<u8>) -> W<u8> {
+        self.shift_right(value)
     }
 
-    fn adc(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.add_with_carry(memory.load(address));
+    fn adc(&mut self, value: W<u8>) -> W<u8> {
+        self.add_with_carry(value);
+        value
     }
 
-    fn ror(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = self.rotate_right(memory.load(address));
-        memory.store(address, m);
+    fn ror(&mut self, value: W<u8>) -> W<u8> {
+        self.rotate_right(value)
     }
 
-    fn sty(&mut self, memory: &mut Mem, address: W<u16>) {
-        memory.store(address, self.Y);
+    fn sty(&mut self, _: W<u8>) -> W<u8> {
+        self.Y
     }
 
-    fn stx(&mut self, memory: &mut Mem, address: W<u16>) {
-        memory.store(address, self.X);
+    fn stx(&mut self, _: W<u8>) -> W<u8> {
+        self.X
     }
 
-    fn sta(&mut self, memory: &mut Mem, address: W<u16>) {
-        memory.store(address, self.A);
+    fn sta(&mut self, _: W<u8>) -> W<u8> {
+        self.A
     }
 
-    fn ldy(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.Y = memory.load(address);
+    fn ldy(&mut self, value: W<u8>) -> W<u8> {
+        self.Y = value;
         set_sign_zero!(self.P, self.Y);
+        value
     }
 
-    fn ldx(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.X = memory.load(address);
+    fn ldx(&mut self, value: W<u8>) -> W<u8> {
+        self.X = value;
         set_sign_zero!(self.P, self.X);
+        value
     }
 
-    fn lda(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.A = memory.load(address);
+    fn lda(&mut self, value: W<u8>) -> W<u8> {
+        self.A = value;
         set_sign_zero!(self.P, self.A);
+        value
     }
 
-    fn cpy(&mut self, memory: &mut Mem, address: W<u16>) {
+    fn cpy(&mut self, value: W<u8>) -> W<u8> {
         let y = self.Y;
-        self.compare(y, memory.load(address));
+        self.compare(y, value);
+        value
     }
 
-    fn cpx(&mut self, memory: &mut Mem, address: W<u16>) {
+    fn cpx(&mut self, value: W<u8>) -> W<u8> {
         let x = self.X;
-        self.compare(x, memory.load(address));
+        self.compare(x, value);
+        value
     }
 
-    fn cmp(&mut self, memory: &mut Mem, address: W<u16>) {
+    fn cmp(&mut self, value: W<u8>) -> W<u8> {
         let a = self.A;
-        self.compare(a, memory.load(address));
+        self.compare(a, value);
+        value
     }
 
-    fn dec(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address) - W(1);
+    fn dec(&mut self, value: W<u8>) -> W<u8> {
+        let m = value - W(1);
         set_sign_zero!(self.P, m);
-        memory.store(address, m);
+        m
     }
 
-    fn sbc(&mut self, memory: &mut Mem, address: W<u16>) {
-        self.add_with_carry(!memory.load(address));
+    fn sbc(&mut self, value: W<u8>) -> W<u8> {
+        self.add_with_carry(!value);
+        value
     }
 
-    fn inc(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address) + W(1);
+    fn inc(&mut self, value: W<u8>) -> W<u8> {
+        let m = value + W(1);
         set_sign_zero!(self.P, m);
-        memory.store(address, m);
+        m
     }
 
     // Unofficial Instructions
 
-    fn lax(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address);
-        self.A = m;
-        self.X = m;
-        set_sign_zero!(self.P, m);
+    fn lax(&mut self, value: W<u8>) -> W<u8> {
+        self.A = value;
+        self.X = value;
+        set_sign_zero!(self.P, value);
+        value
     }
 
-    fn sax(&mut self, memory: &mut Mem, address: W<u16>) {
-        memory.store(address, self.A & self.X);
+    fn sax(&mut self, _: W<u8>) -> W<u8> {
+        self.A & self.X
     }
 
-    fn dcp(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address) - W(1);
+    fn dcp(&mut self, value: W<u8>) -> W<u8> {
+        let m = value - W(1);
         let a = self.A;
-        memory.store(address, m);
         self.compare(a, m);
+        m
     }
 
-    fn isc(&mut self, memory: &mut Mem, address: W<u16>) {
-        let m = memory.load(address) + W(1);
-        memory.store(address, m);
+    fn isc(&mut self, value: W<u8>) -> W<u8> {
+        let m = value + W(1);
         self.add_with_carry(!m);
+        m
     }
 
-    fn slo(&mut self, memory: &mut Mem, address: W<u16>) {
-        let shift = self.shift_left(memory.load(address));
-        memory.store(address, shift);
+    fn slo(&mut self, value: W<u8>) -> W<u8> {
+        let shift = self.shift_left(value);
         self.A |= shift;
         set_sign_zero!(self.P, self.A);
+        shift
     }
 
-    fn rla(&mut self, memory: &mut Mem, address: W<u16>) {
-        let rot = self.rotate_left(memory.load(address));
-        memory.store(address, rot);
+    fn rla(&mut self, value: W<u8>) -> W<u8> {
+        let rot = self.rotate_left(value);
         self.A &= rot;
         set_sign_zero!(self.P, self.A);
+        rot
     }
 
-    fn sre(&mut self, memory: &mut Mem, address: W<u16>) {
-        let shift = self.shift_right(memory.load(address));
-        memory.store(address, shift);
+    fn sre(&mut self, value: W<u8>) -> W<u8> {
+        let shift = self.shift_right(value);
         self.A ^= shift;
         set_sign_zero!(self.P, self.A);
+        shift
     }
 
-    fn rra(&mut self, memory: &mut Mem, address: W<u16>) {
-        let rot = self.rotate_right(memory.load(address));
-        memory.store(address, rot);
+    fn rra(&mut self, value: W<u8>) -> W<u8> {
+        let rot = self.rotate_right(value);
         self.add_with_carry(rot);
+        rot
+    }
+
+    fn anc(&mut self, value: W<u8>) -> W<u8> {
+        self.A &= value;
+        let a = self.A;
+        set_sign_zero_carry_cond!(self.P, a, a & W(0x80) > W(0));
+        value
+    }
+
+    fn alr(&mut self, value: W<u8>) -> W<u8> {
+        let a = self.A & value;
+        self.A = self.shift_right(a);
+        value
+    }
+
+    fn arr(&mut self, value: W<u8>) -> W<u8> {
+        let a = self.A & value;
+        self.A = a >> 1 | (self.P & FLAG_CARRY) << 7;
+        // Carry is bit 6 and overflow is bit 6 xor bit 5 of the result
+        let bit6 = self.A & W(0x40) > W(0);
+        let bit5 = self.A & W(0x20) > W(0);
+        set_sign_zero_carry_cond!(self.P, self.A, bit6);
+        set_flag_cond!(self.P, FLAG_OVERFLOW, bit6 != bit5);
+        value
+    }
+
+    fn axs(&mut self, value: W<u8>) -> W<u8> {
+        let ax = self.A & self.X;
+        self.compare(ax, value);
+        self.X = ax - value;
+        value
+    }
+
+    fn xaa(&mut self, value: W<u8>) -> W<u8> {
+        // Unstable, the constant depends on the chip and temperature
+        self.A = (self.A | W(0xEE)) & self.X & value;
+        set_sign_zero!(self.P, self.A);
+        value
+    }
+
+    fn las(&mut self, value: W<u8>) -> W<u8> {
+        let m = value & self.SP;
+        self.A = m;
+        self.X = m;
+        self.SP = m;
+        set_sign_zero!(self.P, m);
+        value
+    }
+
+    // Unstable stores, they get the high byte of the address plus one
+
+    fn shy(&mut self, high: W<u8>) -> W<u8> {
+        self.Y & high
+    }
+
+    fn shx(&mut self, high: W<u8>) -> W<u8> {
+        self.X & high
+    }
+
+    fn ahx(&mut self, high: W<u8>) -> W<u8> {
+        self.A & self.X & high
+    }
+
+    fn tas(&mut self, high: W<u8>) -> W<u8> {
+        self.SP = self.A & self.X;
+        self.SP & high
     }
 }
 
@@ -761,37 +1348,62 @@ impl fmt::Debug for Regs {
 }
 
 macro_rules! addressing {
-    ($addr:ident, $size:expr) => {
+    ($addr:ident, $size:expr) => (addressing!($addr, $size, $addr));
+    ($addr:ident, $size:expr, $name:ident) => {
         Addressing {
-            function    : Regs::$addr,
+            function    : Execution::$addr,
             size        : W($size),
-            name        : stringify!($addr),
+            name        : stringify!($name),
         }
     }
 }
 
 macro_rules! instruction {
-    ($addr:expr, $oper:ident, $cycles:expr, $extra:expr) => (
+    ($addr:expr, $oper:ident, $func:ident, $cycles:expr, $access:ident) => (
         Instruction {
             mode        : $addr,
-            function    : Regs::$oper,
+            access      : Access::$access,
+            function    : Regs::$func,
             cycles      : $cycles,
-            has_extra   : $extra,
             name        : stringify!($oper),
         }
     )
 }
 
-// Has zero cycle penalty
-macro_rules! iz {
+// Reads its operand
+macro_rules! ir {
+    ($addr:expr, $oper:ident, $cycles:expr) =>
+        (instruction!($addr, $oper, $oper, $cycles, Read))
+}
+
+// Writes its result
+macro_rules! iw {
     ($addr:expr, $oper:ident, $cycles:expr) =>
-        (instruction!($addr, $oper, $cycles, false))
+        (instruction!($addr, $oper, $oper, $cycles, Write))
 }
 
-// Has extra cycle penalty
-macro_rules! ix {
+// Reads, modifies and writes back its operand
+macro_rules! im {
     ($addr:expr, $oper:ident, $cycles:expr) =>
-        (instruction!($addr, $oper, $cycles, true))
+        (instruction!($addr, $oper, $oper, $cycles, Modify))
+}
+
+// Stores a register ANDed with the address high byte plus one
+macro_rules! ih {
+    ($addr:expr, $oper:ident, $cycles:expr) =>
+        (instruction!($addr, $oper, $oper, $cycles, WriteHigh))
+}
+
+// Works only with registers
+macro_rules! ii {
+    ($addr:expr, $oper:ident, $cycles:expr) =>
+        (instruction!($addr, $oper, $oper, $cycles, Implied))
+}
+
+// Control flow, everything is done by its addressing mode
+macro_rules! ic {
+    ($addr:expr, $oper:ident, $cycles:expr) =>
+        (instruction!($addr, $oper, nop, $cycles, Implied))
 }
 
 const IMP : Addressing = addressing!(imp, 1);
@@ -806,87 +1418,97 @@ const IND : Addressing = addressing!(ind, 3);
 const ABX : Addressing = addressing!(abx, 3);
 const ABY : Addressing = addressing!(aby, 3);
 const ABS : Addressing = addressing!(abs, 3);
-
-static INT : &'static Instruction = &iz!(addressing!(abs, 0), int, 7);
+// Named after the operand they show
+const JMP : Addressing = addressing!(jmp, 3, abs);
+const JSR : Addressing = addressing!(jsr, 3, abs);
+const RTS : Addressing = addressing!(rts, 1, imp);
+const RTI : Addressing = addressing!(rti, 1, imp);
+const BRK : Addressing = addressing!(brk, 1, imp);
+const PSH : Addressing = addressing!(psh, 1, imp);
+const PUL : Addressing = addressing!(pul, 1, imp);
+const JAM : Addressing = addressing!(jam, 1, imp);
+
+static INT : &'static Instruction = &ic!(addressing!(int, 0, abs), int, 7);
+static RST : &'static Instruction = &ic!(addressing!(rst, 0, abs), rst, 7);
 static OPCODE_TABLE : &'static [Instruction; 256] = &[
     // 0x00
-    iz!(IMP, brk, 7), iz!(IDX, ora, 6), iz!(IMP, nop, 2), iz!(IDX, slo, 8),
-    iz!(ZPG, nop, 3), iz!(ZPG, ora, 3), iz!(ZPG, asl, 5), iz!(ZPG, slo, 5),
-    iz!(IMP, php, 3), iz!(IMM, ora, 2), iz!(IMP, sal, 2), iz!(IMP, nop, 2),
-    iz!(ABS, nop, 4), iz!(ABS, ora, 4), iz!(ABS, asl, 6), iz!(ABS, slo, 6),
+    ic!(BRK, brk, 7), ir!(IDX, ora, 6), ic!(JAM, jam, 0), im!(IDX, slo, 8),
+    ir!(ZPG, nop, 3), ir!(ZPG, ora, 3), im!(ZPG, asl, 5), im!(ZPG, slo, 5),
+    iw!(PSH, php, 3), ir!(IMM, ora, 2), ii!(IMP, sal, 2), ir!(IMM, anc, 2),
+    ir!(ABS, nop, 4), ir!(ABS, ora, 4), im!(ABS, asl, 6), im!(ABS, slo, 6),
     // 0x10
-    ix!(REL, bpl, 2), ix!(IDY, ora, 5), iz!(IMP, nop, 2), iz!(IDY, slo, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, ora, 4), iz!(ZPX, asl, 6), iz!(ZPX, slo, 6),
-    iz!(IMP, clc, 2), ix!(ABY, ora, 4), iz!(IMP, nop, 2), iz!(ABY, slo, 7),
-    ix!(ABX, nop, 4), ix!(ABX, ora, 4), iz!(ABX, asl, 7), iz!(ABX, slo, 7),
+    ir!(REL, bpl, 2), ir!(IDY, ora, 5), ic!(JAM, jam, 0), im!(IDY, slo, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, ora, 4), im!(ZPX, asl, 6), im!(ZPX, slo, 6),
+    ii!(IMP, clc, 2), ir!(ABY, ora, 4), ii!(IMP, nop, 2), im!(ABY, slo, 7),
+    ir!(ABX, nop, 4), ir!(ABX, ora, 4), im!(ABX, asl, 7), im!(ABX, slo, 7),
     // 0x20
-    iz!(ABS, jsr, 6), iz!(IDX, and, 6), iz!(IMP, nop, 2), iz!(IDX, rla, 8),
-    iz!(ZPG, bit, 3), iz!(ZPG, and, 3), iz!(ZPG, rol, 5), iz!(ZPG, rla, 5),
-    iz!(IMP, plp, 4), iz!(IMM, and, 2), iz!(IMP, ral, 2), iz!(IMP, nop, 2),
-    iz!(ABS, bit, 4), iz!(ABS, and, 4), iz!(ABS, rol, 6), iz!(ABS, rla, 6),
+    ic!(JSR, jsr, 6), ir!(IDX, and, 6), ic!(JAM, jam, 0), im!(IDX, rla, 8),
+    ir!(ZPG, bit, 3), ir!(ZPG, and, 3), im!(ZPG, rol, 5), im!(ZPG, rla, 5),
+    ir!(PUL, plp, 4), ir!(IMM, and, 2), ii!(IMP, ral, 2), ir!(IMM, anc, 2),
+    ir!(ABS, bit, 4), ir!(ABS, and, 4), im!(ABS, rol, 6), im!(ABS, rla, 6),
     // 0x30
-    ix!(REL, bmi, 2), ix!(IDY, and, 5), iz!(IMP, nop, 2), iz!(IDY, rla, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, and, 4), iz!(ZPX, rol, 6), iz!(ZPX, rla, 6),
-    iz!(IMP, sec, 2), ix!(ABY, and, 4), iz!(IMP, nop, 2), iz!(ABY, rla, 7),
-    ix!(ABX, nop, 4), ix!(ABX, and, 4), iz!(ABX, rol, 7), iz!(ABX, rla, 7),
+    ir!(REL, bmi, 2), ir!(IDY, and, 5), ic!(JAM, jam, 0), im!(IDY, rla, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, and, 4), im!(ZPX, rol, 6), im!(ZPX, rla, 6),
+    ii!(IMP, sec, 2), ir!(ABY, and, 4), ii!(IMP, nop, 2), im!(ABY, rla, 7),
+    ir!(ABX, nop, 4), ir!(ABX, and, 4), im!(ABX, rol, 7), im!(ABX, rla, 7),
     // 0x40
-    iz!(IMP, rti, 6), iz!(IDX, eor, 6), iz!(IMP, nop, 2), iz!(IDX, sre, 8),
-    iz!(ZPG, nop, 3), iz!(ZPG, eor, 3), iz!(ZPG, lsr, 5), iz!(ZPG, sre, 5),
-    iz!(IMP, pha, 3), iz!(IMM, eor, 2), iz!(IMP, sar, 2), iz!(IMP, nop, 2),
-    iz!(ABS, jmp, 3), iz!(ABS, eor, 4), iz!(ABS, lsr, 6), iz!(ABS, sre, 6),
+    ic!(RTI, rti, 6), ir!(IDX, eor, 6), ic!(JAM, jam, 0), im!(IDX, sre, 8),
+    ir!(ZPG, nop, 3), ir!(ZPG, eor, 3), im!(ZPG, lsr, 5), im!(ZPG, sre, 5),
+    iw!(PSH, pha, 3), ir!(IMM, eor, 2), ii!(IMP, sar, 2), ir!(IMM, alr, 2),
+    ic!(JMP, jmp, 3), ir!(ABS, eor, 4), im!(ABS, lsr, 6), im!(ABS, sre, 6),
     // 0x50
-    ix!(REL, bvc, 2), ix!(IDY, eor, 5), iz!(IMP, nop, 2), iz!(IDY, sre, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, eor, 4), iz!(ZPX, lsr, 6), iz!(ZPX, sre, 6),
-    iz!(IMP, cli, 2), ix!(ABY, eor, 4), iz!(IMP, nop, 2), iz!(ABY, sre, 7),
-    ix!(ABX, nop, 4), ix!(ABX, eor, 4), iz!(ABX, lsr, 7), iz!(ABX, sre, 7),
+    ir!(REL, bvc, 2), ir!(IDY, eor, 5), ic!(JAM, jam, 0), im!(IDY, sre, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, eor, 4), im!(ZPX, lsr, 6), im!(ZPX, sre, 6),
+    ii!(IMP, cli, 2), ir!(ABY, eor, 4), ii!(IMP, nop, 2), im!(ABY, sre, 7),
+    ir!(ABX, nop, 4), ir!(ABX, eor, 4), im!(ABX, lsr, 7), im!(ABX, sre, 7),
     // 0x60
-    iz!(IMP, rts, 6), iz!(IDX, adc, 6), iz!(IMP, nop, 2), iz!(IDX, rra, 8),
-    iz!(ZPG, nop, 3), iz!(ZPG, adc, 3), iz!(ZPG, ror, 5), iz!(ZPG, rra, 5),
-    iz!(IMP, pla, 4), iz!(IMM, adc, 2), iz!(IMP, rar, 2), iz!(IMP, nop, 2),
-    iz!(IND, jmp, 5), iz!(ABS, adc, 4), iz!(ABS, ror, 6), iz!(ABS, rra, 6),
+    ic!(RTS, rts, 6), ir!(IDX, adc, 6), ic!(JAM, jam, 0), im!(IDX, rra, 8),
+    ir!(ZPG, nop, 3), ir!(ZPG, adc, 3), im!(ZPG, ror, 5), im!(ZPG, rra, 5),
+    ir!(PUL, pla, 4), ir!(IMM, adc, 2), ii!(IMP, rar, 2), ir!(IMM, arr, 2),
+    ic!(IND, jmp, 5), ir!(ABS, adc, 4), im!(ABS, ror, 6), im!(ABS, rra, 6),
     // 0x70
-    ix!(REL, bvs, 2), ix!(IDY, adc, 5), iz!(IMP, nop, 2), iz!(IDY, rra, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, adc, 4), iz!(ZPX, ror, 6), iz!(ZPX, rra, 6),
-    iz!(IMP, sei, 2), ix!(ABY, adc, 4), iz!(IMP, nop, 2), iz!(ABY, rra, 7),
-    ix!(ABX, nop, 4), ix!(ABX, adc, 4), iz!(ABX, ror, 7), iz!(ABX, rra, 7),
+    ir!(REL, bvs, 2), ir!(IDY, adc, 5), ic!(JAM, jam, 0), im!(IDY, rra, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, adc, 4), im!(ZPX, ror, 6), im!(ZPX, rra, 6),
+    ii!(IMP, sei, 2), ir!(ABY, adc, 4), ii!(IMP, nop, 2), im!(ABY, rra, 7),
+    ir!(ABX, nop, 4), ir!(ABX, adc, 4), im!(ABX, ror, 7), im!(ABX, rra, 7),
     // 0x80
-    iz!(IMM, nop, 2), iz!(IDX, sta, 6), iz!(IMM, nop, 2), iz!(IDX, sax, 6),
-    iz!(ZPG, sty, 3), iz!(ZPG, sta, 3), iz!(ZPG, stx, 3), iz!(ZPG, sax, 3),
-    iz!(IMP, dey, 2), iz!(IMM, nop, 2), iz!(IMP, txa, 2), iz!(IMP, nop, 2),
-    iz!(ABS, sty, 4), iz!(ABS, sta, 4), iz!(ABS, stx, 4), iz!(ABS, sax, 4),
+    ir!(IMM, nop, 2), iw!(IDX, sta, 6), ir!(IMM, nop, 2), iw!(IDX, sax, 6),
+    iw!(ZPG, sty, 3), iw!(ZPG, sta, 3), iw!(ZPG, stx, 3), iw!(ZPG, sax, 3),
+    ii!(IMP, dey, 2), ir!(IMM, nop, 2), ii!(IMP, txa, 2), ir!(IMM, xaa, 2),
+    iw!(ABS, sty, 4), iw!(ABS, sta, 4), iw!(ABS, stx, 4), iw!(ABS, sax, 4),
     // 0x90
-    ix!(REL, bcc, 2), iz!(IDY, sta, 6), iz!(IMP, nop, 2), iz!(IMP, nop, 2),
-    iz!(ZPX, sty, 4), iz!(ZPX, sta, 4), iz!(ZPY, stx, 4), iz!(ZPY, sax, 4),
-    iz!(IMP, tya, 2), iz!(ABY, sta, 5), iz!(IMP, txs, 2), iz!(IMP, nop, 2),
-    iz!(IMP, nop, 2), iz!(ABX, sta, 5), iz!(IMP, nop, 2), iz!(IMP, nop, 2),
+    ir!(REL, bcc, 2), iw!(IDY, sta, 6), ic!(JAM, jam, 0), ih!(IDY, ahx, 6),
+    iw!(ZPX, sty, 4), iw!(ZPX, sta, 4), iw!(ZPY, stx, 4), iw!(ZPY, sax, 4),
+    ii!(IMP, tya, 2), iw!(ABY, sta, 5), ii!(IMP, txs, 2), ih!(ABY, tas, 5),
+    ih!(ABX, shy, 5), iw!(ABX, sta, 5), ih!(ABY, shx, 5), ih!(ABY, ahx, 5),
     // 0xA0
-    iz!(IMM, ldy, 2), iz!(IDX, lda, 6), iz!(IMM, ldx, 2), iz!(IDX, lax, 6),
-    iz!(ZPG, ldy, 3), iz!(ZPG, lda, 3), iz!(ZPG, ldx, 3), iz!(ZPG, lax, 3),
-    iz!(IMP, tay, 2), iz!(IMM, lda, 2), iz!(IMP, tax, 2), iz!(IMM, lax, 2),
-    iz!(ABS, ldy, 4), iz!(ABS, lda, 4), iz!(ABS, ldx, 4), iz!(ABS, lax, 4),
+    ir!(IMM, ldy, 2), ir!(IDX, lda, 6), ir!(IMM, ldx, 2), ir!(IDX, lax, 6),
+    ir!(ZPG, ldy, 3), ir!(ZPG, lda, 3), ir!(ZPG, ldx, 3), ir!(ZPG, lax, 3),
+    ii!(IMP, tay, 2), ir!(IMM, lda, 2), ii!(IMP, tax, 2), ir!(IMM, lax, 2),
+    ir!(ABS, ldy, 4), ir!(ABS, lda, 4), ir!(ABS, ldx, 4), ir!(ABS, lax, 4),
     // 0xB0
-    ix!(REL, bcs, 2), ix!(IDY, lda, 5), iz!(IMP, nop, 2), ix!(IDY, lax, 5),
-    iz!(ZPX, ldy, 4), iz!(ZPX, lda, 4), iz!(ZPY, ldx, 4), iz!(ZPY, lax, 4),
-    iz!(IMP, clv, 2), ix!(ABY, lda, 4), iz!(IMP, tsx, 2), iz!(IMP, nop, 2),
-    ix!(ABX, ldy, 4), ix!(ABX, lda, 4), ix!(ABY, ldx, 4), ix!(ABY, lax, 4),
+    ir!(REL, bcs, 2), ir!(IDY, lda, 5), ic!(JAM, jam, 0), ir!(IDY, lax, 5),
+    ir!(ZPX, ldy, 4), ir!(ZPX, lda, 4), ir!(ZPY, ldx, 4), ir!(ZPY, lax, 4),
+    ii!(IMP, clv, 2), ir!(ABY, lda, 4), ii!(IMP, tsx, 2), ir!(ABY, las, 4),
+    ir!(ABX, ldy, 4), ir!(ABX, lda, 4), ir!(ABY, ldx, 4), ir!(ABY, lax, 4),
     // 0xC0
-    iz!(IMM, cpy, 2), iz!(IDX, cmp, 6), iz!(IMM, nop, 2), iz!(IDX, dcp, 8),
-    iz!(ZPG, cpy, 3), iz!(ZPG, cmp, 3), iz!(ZPG, dec, 5), iz!(ZPG, dcp, 5),
-    iz!(IMP, iny, 2), iz!(IMM, cmp, 2), iz!(IMP, dex, 2), iz!(IMP, nop, 2),
-    iz!(ABS, cpy, 4), iz!(ABS, cmp, 4), iz!(ABS, dec, 6), iz!(ABS, dcp, 6),
+    ir!(IMM, cpy, 2), ir!(IDX, cmp, 6), ir!(IMM, nop, 2), im!(IDX, dcp, 8),
+    ir!(ZPG, cpy, 3), ir!(ZPG, cmp, 3), im!(ZPG, dec, 5), im!(ZPG, dcp, 5),
+    ii!(IMP, iny, 2), ir!(IMM, cmp, 2), ii!(IMP, dex, 2), ir!(IMM, axs, 2),
+    ir!(ABS, cpy, 4), ir!(ABS, cmp, 4), im!(ABS, dec, 6), im!(ABS, dcp, 6),
     // 0xD0
-    ix!(REL, bne, 2), ix!(IDY, cmp, 5), iz!(IMP, nop, 2), iz!(IDY, dcp, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, cmp, 4), iz!(ZPX, dec, 6), iz!(ZPX, dcp, 6),
-    iz!(IMP, cld, 2), ix!(ABY, cmp, 4), iz!(IMP, nop, 2), iz!(ABY, dcp, 7),
-    ix!(ABX, nop, 4), ix!(ABX, cmp, 4), iz!(ABX, dec, 7), iz!(ABX, dcp, 7),
+    ir!(REL, bne, 2), ir!(IDY, cmp, 5), ic!(JAM, jam, 0), im!(IDY, dcp, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, cmp, 4), im!(ZPX, dec, 6), im!(ZPX, dcp, 6),
+    ii!(IMP, cld, 2), ir!(ABY, cmp, 4), ii!(IMP, nop, 2), im!(ABY, dcp, 7),
+    ir!(ABX, nop, 4), ir!(ABX, cmp, 4), im!(ABX, dec, 7), im!(ABX, dcp, 7),
     // 0xE0
-    iz!(IMM, cpx, 2), iz!(IDX, sbc, 6), iz!(IMM, nop, 2), iz!(IDX, isc, 8),
-    iz!(ZPG, cpx, 3), iz!(ZPG, sbc, 3), iz!(ZPG, inc, 5), iz!(ZPG, isc, 5),
-    iz!(IMP, inx, 2), iz!(IMM, sbc, 2), iz!(IMP, nop, 2), iz!(IMM, sbc, 2),
-    iz!(ABS, cpx, 4), iz!(ABS, sbc, 4), iz!(ABS, inc, 6), iz!(ABS, isc, 6),
+    ir!(IMM, cpx, 2), ir!(IDX, sbc, 6), ir!(IMM, nop, 2), im!(IDX, isc, 8),
+    ir!(ZPG, cpx, 3), ir!(ZPG, sbc, 3), im!(ZPG, inc, 5), im!(ZPG, isc, 5),
+    ii!(IMP, inx, 2), ir!(IMM, sbc, 2), ii!(IMP, nop, 2), ir!(IMM, sbc, 2),
+    ir!(ABS, cpx, 4), ir!(ABS, sbc, 4), im!(ABS, inc, 6), im!(ABS, isc, 6),
     // 0xF0
-    ix!(REL, beq, 2), ix!(IDY, sbc, 5), iz!(IMP, nop, 2), iz!(IDY, isc, 8),
-    iz!(ZPX, nop, 4), iz!(ZPX, sbc, 4), iz!(ZPX, inc, 6), iz!(ZPX, isc, 6),
-    iz!(IMP, sed, 2), ix!(ABY, sbc, 4), iz!(IMP, nop, 2), iz!(ABY, isc, 7),
-    ix!(ABX, nop, 4), ix!(ABX, sbc, 4), iz!(ABX, inc, 7), iz!(ABX, isc, 7),
+    ir!(REL, beq, 2), ir!(IDY, sbc, 5), ic!(JAM, jam, 0), im!(IDY, isc, 8),
+    ir!(ZPX, nop, 4), ir!(ZPX, sbc, 4), im!(ZPX, inc, 6), im!(ZPX, isc, 6),
+    ii!(IMP, sed, 2), ir!(ABY, sbc, 4), ii!(IMP, nop, 2), im!(ABY, isc, 7),
+    ir!(ABX, nop, 4), ir!(ABX, sbc, 4), im!(ABX, inc, 7), im!(ABX, isc, 7),
 ];
@@ -1,13 +1,16 @@
 // nes
 use nes::Nes;
 use cpu::Operation;
+use trace::{Trace, parse_range};
 // std
 use std::io;
 use std::io::Write;
+use std::num::Wrapping as W;
+
+const DEBUG_LIST_SIZE : u32 = 10;
 
 pub fn run(nes: &mut Nes) {
     let mut command : Vec<String> = vec![String::from("")];
-    nes.reset();
     'debug: loop {
         let mut input = String::new();
         print!("(rdbg) ");
@@ -19,7 +22,7 @@ pub fn run(nes: &mut Nes) {
             command = words
         }
         match command[0].as_ref() {
-            // "l"|"list" => print_list(nes, DEBUG_LIST_SIZE),
+            "l"|"list" => print_list(nes, DEBUG_LIST_SIZE),
             // Over function calls
             "n"|"next" => {
                 next(nes);
@@ -40,6 +43,19 @@ pub fn run(nes: &mut Nes) {
             "u"|"until" => {
                 until(nes);
             }
+            "trace" => {
+                set_trace(nes, &command[1..]);
+            },
+            "cdl" => {
+                code_data_log(nes, &command[1..]);
+            },
+            "events" => {
+                ppu_events(nes, &command[1..]);
+            },
+            "reset" => {
+                nes.soft_reset();
+                println!("Reset, the next step runs the reset sequence");
+            },
             "p" => {
                 if command.len() == 1 {
                     println!("No register or memory position given");
@@ -55,6 +71,13 @@ pub fn run(nes: &mut Nes) {
                     print_reg_binary(nes, &command[1]);
                 }
             },
+            "pv" => {
+                if command.len() == 1 {
+                    println!("No PPU memory position given");
+                } else {
+                    print_ppu_memory(nes, &command[1]);
+                }
+            },
             "b" => println!("breakpoint"),
             "q"|"quit" => {
                 break 'debug;
@@ -66,6 +89,63 @@ pub fn run(nes: &mut Nes) {
     }
 }
 
+fn set_trace(nes: &mut Nes, args: &[String]) {
+    if args.is_empty() {
+        println!("No trace file given");
+        return;
+    }
+    if args[0] == "off" {
+        nes.set_trace(None);
+        println!("Trace stopped");
+        return;
+    }
+    let ranges: Result<Vec<(u16, u16)>, String> =
+        args[1..].iter().map(|range| parse_range(range)).collect();
+    match ranges.and_then(|ranges| Trace::to_file(&args[0], ranges)) {
+        Ok(trace) => {
+            nes.set_trace(Some(trace));
+            println!("Tracing to {}", args[0]);
+        },
+        Err(error) => println!("{}", error),
+    }
+}
+
+fn code_data_log(nes: &mut Nes, args: &[String]) {
+    match (args.get(0).map(|arg| arg.as_ref()), args.get(1)) {
+        (Some("start"), None) => {
+            let _ = nes.start_cdl(None);
+            println!("Code/data logger started");
+        },
+        (Some("stop"), None) => {
+            nes.stop_cdl();
+            println!("Code/data logger stopped");
+        },
+        (Some("save"), Some(path)) => match nes.save_cdl(path) {
+            Ok(()) => println!("Code/data log saved to {}", path),
+            Err(error) => println!("{}", error),
+        },
+        _ => println!("Usage: cdl start|stop|save FILE"),
+    }
+}
+
+fn ppu_events(nes: &mut Nes, args: &[String]) {
+    match (args.get(0).map(|arg| arg.as_ref()), args.get(1)) {
+        (Some("start"), None) => {
+            nes.start_events();
+            println!("PPU event logger started");
+        },
+        (Some("stop"), None) => {
+            nes.stop_events();
+            println!("PPU event logger stopped");
+        },
+        (Some("save"), Some(path)) => match nes.save_events(path) {
+            Ok(()) => println!("Events of the last frame saved to {}", path),
+            Err(error) => println!("{}", error),
+        },
+        _ => println!("Usage: events start|stop|save FILE"),
+    }
+}
+
 fn step_cycle(nes: &mut Nes) {
     nes.cycle();
     print_current_operation(nes);
@@ -77,41 +157,49 @@ pub fn step(nes: &mut Nes) {
 }
 
 fn next(nes: &mut Nes) {
-    let instruction = nes.cpu().execution().operation.inst;
+    nes.step();
+    let execution = nes.cpu().execution();
+    let instruction = execution.operation.inst;
     if instruction.name == "jsr" {
-        let pc = nes.cpu().registers().PC + instruction.mode.size;
-        while nes.cpu().registers().PC != pc {
+        let pc = execution.pc + instruction.mode.size;
+        while nes.cpu().registers().PC != pc && !nes.cpu().jammed() {
             nes.step();
         }
-    } else {
-        nes.step();
     }
     print_current_operation(nes);
 }
 
 fn until(nes: &mut Nes) {
     let pc = nes.cpu().registers().PC;
-    while nes.cpu().registers().PC <= pc {
+    while nes.cpu().registers().PC <= pc && !nes.cpu().jammed() {
         nes.step();
     }
     print_current_operation(nes);
 }
 
+// Prints the last executed operation
 fn print_current_operation(nes: &Nes) {
-    print!("{:04X} ", nes.cpu().registers().PC);
     let execution = &nes.cpu().execution();
-    print_operation(&execution.operation, execution.address.0);
+    print!("{:04X} ", execution.pc.0);
+    print_operation(&execution.operation, Some(execution.address.0));
     println!("");
+    if nes.cpu().jammed() {
+        println!("CPU jammed, only a reset recovers it");
+    }
 }
 
-fn print_operation(operation: &Operation, address: u16) {
+// The effective address is only known after the operation executes
+fn print_operation(operation: &Operation, address: Option<u16>) {
     let inst = operation.inst;
     let operand = operation.operand.0;
     print!("{} ", inst.name);
     match inst.mode.name {
         "imp" => print!(""),
         "imm" => print!("#{:02X}", operand),
-        "rel" => print!("{:04X}", address),
+        "rel" => match address {
+            Some(address) => print!("{:04X}", address),
+            None => print!("{:+}", operand as i8),
+        },
         "abs" => print!("{:04X}", operand),
         "abx" => print!("{:04X},X", operand),
         "aby" => print!("{:04X},Y", operand),
@@ -123,23 +211,29 @@ fn print_operation(operation: &Operation, address: u16) {
         "zpy" => print!("{:02X},Y", operand),
         _  => print!("Invalid Mode"),
     }
-    match inst.mode.name {
-        "imp" | "imm" | "rel" | "abs" => {},
-        _ => {print!(" @ {:04X}", address)},
+    match (inst.mode.name, address) {
+        ("imp", _) | ("imm", _) | ("rel", _) | ("abs", _) | (_, None) => {},
+        (_, Some(address)) => print!(" @ {:04X}", address),
     }
 }
 
-/* TODO: Rethink this function, from_address modifies nes.memory()
-         And we also need the execution
-fn print_list(nes: &mut Nes, count: u32) {
+fn print_list(nes: &Nes, count: u32) {
     let mut pc = nes.cpu().registers().PC;
     for _ in 0..count {
         let operation = Operation::from_address(nes.memory(), pc);
-        print_operation(&operation, 0);
-        pc = pc + operation.inst.mode.size;
+        print!("{:04X} ", pc.0);
+        let size = operation.inst.mode.size;
+        // Branch targets are known without executing
+        let target = if operation.inst.mode.name == "rel" {
+            Some((pc + size + W(operation.operand.0 as i8 as u16)).0)
+        } else {
+            None
+        };
+        print_operation(&operation, target);
+        println!("");
+        pc = pc + size;
     }
 }
-*/
 
 fn print_reg(nes: &Nes, reg: &str) {
     let ureg = reg.to_uppercase();
@@ -151,7 +245,23 @@ fn print_reg_binary(nes: &Nes, reg: &str) {
     println!("{}: {:b}", ureg, get_reg(nes, ureg.as_ref()));
 }
 
+fn print_ppu_memory(nes: &Nes, position: &str) {
+    let position = if position.starts_with('$') {&position[1..]} else {position};
+    match u16::from_str_radix(position, 16) {
+        Ok(address) => println!("${:04X}: {:x}", address,
+                                nes.ppu_peek(W(address)).0),
+        Err(_) => println!("Invalid PPU memory position {}", position),
+    }
+}
+
+// Registers by name or memory positions as $XXXX
 fn get_reg(nes: &Nes, reg: &str) -> u16 {
+    if reg.starts_with('$') {
+        return match u16::from_str_radix(&reg[1..], 16) {
+            Ok(address) => nes.peek(W(address)).0 as u16,
+            Err(_) => {println!("Error invalid address returning 0"); 0},
+        };
+    }
     match reg {
         "A"     => nes.cpu().registers().A.0 as u16,
         "X"     => nes.cpu().registers().X.0 as u16,
@@ -169,9 +279,15 @@ fn help() {
     println!("'s', 'step', to execute next instruction");
     println!("'n', 'next', to execute next instruction over function calls");
     println!("'cycle', to execute a single cycle");
+    println!("'reset', to press the reset button");
+    println!("'trace' plus a file and optional PC ranges to log instructions (ex: trace out.log C000-C7FF).");
+    println!("'trace off' to stop logging.");
+    println!("'cdl start', 'cdl stop' or 'cdl save' plus a file for the code/data logger.");
+    println!("'events start', 'events stop' or 'events save' plus a file to log PPU register writes and mapper IRQs.");
     println!("'b' or 'breakpoint' for breakpoints (NOT IMPLEMENTED YET).");
     println!("'l' or 'list' to show the next instructions to be executed");
-    println!("'p' plus a register name to show the value of the register (ex: p A).");
+    println!("'p' plus a register name or memory position to show its value (ex: p A, p $0200).");
     println!("'pb' to show that value in binary (ex: pb A).");
+    println!("'pv' plus a PPU memory position to show its value (ex: pv $3F00).");
     println!("'q' or 'quit' to quit.");
 }
@@ -0,0 +1,529 @@
+// nes
+use header::Header;
+use cpu::Operation;
+// std
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+// Banks bigger than this don't fit in the CPU address space
+const MAX_BANK_SIZE     : usize = 0x8000;
+// Switchable banks at $8000 of the mappers with one fixed at $C000
+const SPLIT_BANK_SIZE   : usize = 0x4000;
+// NROM and CNROM don't switch PRG
+const FIXED_PRG_MAPPERS : [u16; 2] = [0, 3];
+// AxROM, Color Dreams, BxROM and GxROM switch all 32KiB, vectors included
+const WHOLE_BANK_MAPPERS: [u16; 4] = [7, 11, 34, 66];
+const VECTORS_SIZE      : usize = 6;
+const BYTES_PER_LINE    : usize = 16;
+const VECTOR_NAMES      : [&'static str; 3] = ["nmi", "reset", "irq"];
+
+const REGISTERS : [(u16, &'static str); 12] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "APUSTATUS"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Byte {
+    Unknown,
+    Opcode,
+    Operand,
+    Vector,
+}
+
+// A PRG bank as it is mapped in the CPU address space
+struct Bank<'a> {
+    data        : &'a [u8],
+    org         : u16,
+    // Mapped at $8000 and $C000 when it's 16KiB
+    mirrored    : bool,
+    kind        : Vec<Byte>,
+    // Offset of the instruction or vector each byte belongs to
+    start       : Vec<usize>,
+    labels      : BTreeMap<usize, String>,
+}
+
+impl<'a> Bank<'a> {
+    fn new(data: &'a [u8], org: u16, mirrored: bool) -> Bank<'a> {
+        Bank {
+            data        : data,
+            org         : org,
+            mirrored    : mirrored,
+            kind        : vec![Byte::Unknown; data.len()],
+            start       : (0..data.len()).collect(),
+            labels      : BTreeMap::new(),
+        }
+    }
+
+    // Offset of a CPU address
+    fn offset(&self, address: u16) -> Option<usize> {
+        if self.mirrored && address >= 0x8000 {
+            Some(address as usize & (self.data.len() - 1))
+        } else {
+            self.label_offset(address)
+        }
+    }
+
+    // Mirrored addresses are left as numbers to keep the operand bytes
+    fn label_offset(&self, address: u16) -> Option<usize> {
+        let offset = (address as usize).wrapping_sub(self.org as usize);
+        if offset < self.data.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn word(&self, offset: usize) -> u16 {
+        self.data[offset] as u16 | (self.data[offset + 1] as u16) << 8
+    }
+
+    fn add_label(&mut self, address: u16) {
+        if let Some(offset) = self.label_offset(address) {
+            self.labels.entry(offset)
+                       .or_insert_with(|| format!("L_{:04X}", address));
+        }
+    }
+
+    // Banks without the vectors have no known entry points, they are left
+    // as data
+    fn analyze(&mut self) {
+        if (self.org as usize + self.data.len()) < 0x10000 {
+            return;
+        }
+        let vectors = self.data.len() - VECTORS_SIZE;
+        let mut entries = Vec::new();
+        for (i, name) in VECTOR_NAMES.iter().enumerate() {
+            let offset = vectors + i * 2;
+            self.kind[offset] = Byte::Vector;
+            self.kind[offset + 1] = Byte::Vector;
+            self.start[offset + 1] = offset;
+            let address = self.word(offset);
+            if let Some(target) = self.label_offset(address) {
+                self.labels.entry(target).or_insert(name.to_string());
+            }
+            entries.push(address);
+        }
+        for address in entries {
+            self.trace(address);
+        }
+        // Labels pointing inside an instruction are defined at its start
+        let labels: Vec<usize> = self.labels.keys().cloned().collect();
+        for offset in labels {
+            let start = self.start[offset];
+            if start != offset && !self.labels.contains_key(&start) {
+                let address = self.org as usize + start;
+                self.labels.insert(start, format!("L_{:04X}", address));
+            }
+        }
+    }
+
+    // Follows every reachable path from address marking the code bytes
+    fn trace(&mut self, address: u16) {
+        let mut pending = vec![address];
+        while let Some(mut pc) = pending.pop() {
+            while let Some(offset) = self.offset(pc) {
+                let operation = Operation::from_opcode(self.data[offset]);
+                let inst = operation.inst;
+                let size = inst.mode.size.0 as usize;
+                if inst.name == "jam" || offset + size > self.data.len() ||
+                   (0..size).any(|i| self.kind[offset + i] != Byte::Unknown) {
+                    break;
+                }
+                self.kind[offset] = Byte::Opcode;
+                for i in 1..size {
+                    self.kind[offset + i] = Byte::Operand;
+                    self.start[offset + i] = offset;
+                }
+                let operand = match size {
+                    2 => self.data[offset + 1] as u16,
+                    3 => self.word(offset + 1),
+                    _ => 0,
+                };
+                let next = pc.wrapping_add(size as u16);
+                match (inst.name, inst.mode.name) {
+                    ("jmp", "abs") => {
+                        self.add_label(operand);
+                        pending.push(operand);
+                        break;
+                    },
+                    ("jsr", _) => {
+                        self.add_label(operand);
+                        pending.push(operand);
+                    },
+                    ("jmp", _) => {
+                        self.add_label(operand);
+                        break;
+                    },
+                    ("rts", _) | ("rti", _) | ("brk", _) => break,
+                    (_, "rel") => {
+                        let target = next.wrapping_add(operand as i8 as u16);
+                        self.add_label(target);
+                        pending.push(target);
+                    },
+                    (_, "abs") | (_, "abx") | (_, "aby") => {
+                        self.add_label(operand);
+                    },
+                    _ => (),
+                }
+                // Wrapping past $FFFF ends the trace
+                if next < pc {
+                    break;
+                }
+                pc = next;
+            }
+        }
+    }
+
+    // An address as a label, register name or number
+    fn reference(&self, address: u16) -> String {
+        if let Some(offset) = self.label_offset(address) {
+            let start = self.start[offset];
+            if let Some(label) = self.labels.get(&start) {
+                return if start == offset {
+                    label.clone()
+                } else {
+                    format!("{}+{}", label, offset - start)
+                };
+            }
+        }
+        match REGISTERS.iter().find(|&&(register, _)| register == address) {
+            Some(&(_, name)) => name.to_string(),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    // ca65 would pick zero page for small absolute operands
+    fn absolute(&self, address: u16) -> String {
+        if address < 0x100 {
+            format!("a:${:04X}", address)
+        } else {
+            self.reference(address)
+        }
+    }
+
+    fn instruction(&self, offset: usize) -> String {
+        let operation = Operation::from_opcode(self.data[offset]);
+        let inst = operation.inst;
+        let name = inst.name;
+        let size = inst.mode.size.0 as usize;
+        let bytes = &self.data[offset..offset + size];
+        // ca65 either doesn't know these or assembles them to another
+        // opcode, bytes keep the output identical to the ROM
+        if !operation.is_official() {
+            let data: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b))
+                                                .collect();
+            return format!(".byte {}", data.join(", "));
+        }
+        let zero_page = if size > 1 {bytes[1]} else {0};
+        let operand = if size > 2 {self.word(offset + 1)} else {zero_page as u16};
+        let address = self.org.wrapping_add(offset as u16);
+        match (name, inst.mode.name) {
+            ("sal", _) => "asl a".to_string(),
+            ("sar", _) => "lsr a".to_string(),
+            ("ral", _) => "rol a".to_string(),
+            ("rar", _) => "ror a".to_string(),
+            (_, "imp") => name.to_string(),
+            (_, "imm") => format!("{} #${:02X}", name, zero_page),
+            (_, "rel") => {
+                let target = address.wrapping_add(2)
+                                    .wrapping_add(zero_page as i8 as u16);
+                format!("{} {}", name, self.reference(target))
+            },
+            ("jmp", "abs") | ("jsr", _) =>
+                format!("{} {}", name, self.reference(operand)),
+            (_, "abs") => format!("{} {}", name, self.absolute(operand)),
+            (_, "abx") => format!("{} {},x", name, self.absolute(operand)),
+            (_, "aby") => format!("{} {},y", name, self.absolute(operand)),
+            (_, "ind") => format!("{} ({})", name, self.reference(operand)),
+            (_, "idx") => format!("{} (${:02X},x)", name, zero_page),
+            (_, "idy") => format!("{} (${:02X}),y", name, zero_page),
+            (_, "zpg") => format!("{} ${:02X}", name, zero_page),
+            (_, "zpx") => format!("{} ${:02X},x", name, zero_page),
+            (_, "zpy") => format!("{} ${:02X},y", name, zero_page),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&self, output: &mut String, index: usize) {
+        let _ = writeln!(output, ".segment \"BANK_{:02}\"", index);
+        let _ = writeln!(output, ".scope bank_{:02}", index);
+        let _ = writeln!(output, "    .org ${:04X}", self.org);
+        let mut data = Vec::new();
+        let mut offset = 0;
+        while offset < self.data.len() {
+            let label = self.labels.get(&offset);
+            let kind = self.kind[offset];
+            if !data.is_empty() &&
+               (label.is_some() || kind != Byte::Unknown ||
+                data.len() == BYTES_PER_LINE) {
+                write_bytes(output, &mut data);
+            }
+            if let Some(label) = label {
+                let _ = writeln!(output, "{}:", label);
+            }
+            let address = self.org as usize + offset;
+            match kind {
+                Byte::Opcode => {
+                    let inst = Operation::from_opcode(self.data[offset]).inst;
+                    let size = inst.mode.size.0 as usize;
+                    let text = self.instruction(offset);
+                    if text.starts_with(".byte") {
+                        let _ = writeln!(output, "    {:<28}; {:04X} {}",
+                                         text, address, inst.name);
+                    } else {
+                        let _ = writeln!(output, "    {:<28}; {:04X}",
+                                         text, address);
+                    }
+                    offset += size;
+                },
+                Byte::Vector => {
+                    let _ = writeln!(output, "    .addr {}",
+                                     self.reference(self.word(offset)));
+                    offset += 2;
+                },
+                _ => {
+                    data.push(self.data[offset]);
+                    offset += 1;
+                },
+            }
+        }
+        write_bytes(output, &mut data);
+        let _ = writeln!(output, ".endscope\n");
+    }
+}
+
+fn write_bytes(output: &mut String, data: &mut Vec<u8>) {
+    if !data.is_empty() {
+        let bytes: Vec<String> = data.iter().map(|b| format!("${:02X}", b))
+                                            .collect();
+        let _ = writeln!(output, "    .byte {}", bytes.join(", "));
+        data.clear();
+    }
+}
+
+fn write_segment(output: &mut String, name: &str, data: &[u8]) {
+    let _ = writeln!(output, ".segment \"{}\"", name);
+    for line in data.chunks(BYTES_PER_LINE) {
+        write_bytes(output, &mut line.to_vec());
+    }
+    let _ = writeln!(output, "");
+}
+
+// PRG banks as the mapper switches them. Whole 32KiB banks each have their
+// own vectors, otherwise 16KiB banks are switched at $8000 and the last one
+// stays at $C000, as on UxROM, MMC1 and, in 8KiB halves, MMC3.
+fn prg_banks(prg_rom: &[u8], mapper: u16) -> Vec<Bank> {
+    let fixed = FIXED_PRG_MAPPERS.contains(&mapper);
+    if prg_rom.len() <= SPLIT_BANK_SIZE ||
+       (fixed && prg_rom.len() <= MAX_BANK_SIZE) {
+        let org = (0x10000 - prg_rom.len()) as u16;
+        vec![Bank::new(prg_rom, org, true)]
+    } else if fixed || WHOLE_BANK_MAPPERS.contains(&mapper) {
+        prg_rom.chunks(MAX_BANK_SIZE)
+               .map(|data| Bank::new(data, 0x8000, false)).collect()
+    } else {
+        let last = prg_rom.len() / SPLIT_BANK_SIZE - 1;
+        prg_rom.chunks(SPLIT_BANK_SIZE).enumerate().map(|(i, data)| {
+            Bank::new(data, if i == last {0xC000} else {0x8000}, false)
+        }).collect()
+    }
+}
+
+// Disassembles a whole ROM into ca65 source, code is traced from the
+// vectors of the banks that have them
+pub fn disassemble<P: AsRef<Path>>(rom_path: P) -> Result<String, String> {
+    let mut header = Header::load_rom(rom_path)?;
+    let trainer = try_err!(header.get_trainer(), "Couldn't read ROM trainer");
+    let mem = try_err!(header.get_game_memory(), "Couldn't read ROM data");
+    if mem.prg_rom.is_empty() {
+        return err!("ROM has no PRG data");
+    }
+    let mut banks = prg_banks(&mem.prg_rom, header.mapper_number());
+    for bank in banks.iter_mut() {
+        bank.analyze();
+    }
+    let mut output = String::new();
+    let _ = writeln!(output, "; Disassembled by warnes, link with ld65 using:");
+    let _ = writeln!(output, "; MEMORY {{");
+    let _ = writeln!(output, ";     HDR: start = $0000, size = $10, file = %O, fill = yes;");
+    if !trainer.is_empty() {
+        let _ = writeln!(output, ";     TRN: start = $7000, size = $200, file = %O, fill = yes;");
+    }
+    for (i, bank) in banks.iter().enumerate() {
+        let _ = writeln!(output, ";     B{:02}: start = ${:04X}, size = ${:04X}, file = %O, fill = yes;",
+                         i, bank.org, bank.data.len());
+    }
+    if !mem.chr_rom.is_empty() {
+        let _ = writeln!(output, ";     CHR: start = $0000, size = ${:04X}, file = %O, fill = yes;",
+                         mem.chr_rom.len());
+    }
+    let _ = writeln!(output, "; }}\n; SEGMENTS {{");
+    let _ = writeln!(output, ";     HEADER: load = HDR, type = ro;");
+    if !trainer.is_empty() {
+        let _ = writeln!(output, ";     TRAINER: load = TRN, type = ro;");
+    }
+    for i in 0..banks.len() {
+        let _ = writeln!(output, ";     BANK_{:02}: load = B{:02}, type = ro;", i, i);
+    }
+    if !mem.chr_rom.is_empty() {
+        let _ = writeln!(output, ";     CHARS: load = CHR, type = ro;");
+    }
+    let _ = writeln!(output, "; }}\n");
+    let _ = writeln!(output, ".setcpu \"6502\"\n");
+    for &(address, name) in REGISTERS.iter() {
+        let _ = writeln!(output, "{:<12}= ${:04X}", name, address);
+    }
+    let _ = writeln!(output, "");
+    write_segment(&mut output, "HEADER", header.file_header());
+    if !trainer.is_empty() {
+        write_segment(&mut output, "TRAINER", &trainer);
+    }
+    for (i, bank) in banks.iter().enumerate() {
+        bank.write(&mut output, i);
+    }
+    if !mem.chr_rom.is_empty() {
+        write_segment(&mut output, "CHARS", &mem.chr_rom);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use disasm::disassemble;
+    // std
+    use std::env;
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    const BRANCHES : [&'static str; 8] =
+        ["bpl", "bmi", "bvc", "bvs", "bcc", "bcs", "bne", "beq"];
+
+    // Lines of the first PRG bank scope
+    fn bank_lines(source: &str) -> Vec<&str> {
+        scope_lines(source, 0)
+    }
+
+    fn scope_lines(source: &str, bank: usize) -> Vec<&str> {
+        let scope = format!(".scope bank_{:02}", bank);
+        source.lines().skip_while(|line| *line != scope)
+                      .skip(2)
+                      .take_while(|line| *line != ".endscope")
+                      .collect()
+    }
+
+    // Bytes ca65 assembles an instruction to
+    fn instruction_size(text: &str) -> usize {
+        let mut parts = text.splitn(2, ' ');
+        let name = parts.next().unwrap();
+        let operand = parts.next().unwrap_or("").trim_start_matches('(');
+        if BRANCHES.contains(&name) {
+            2
+        } else if operand.is_empty() || operand == "a" {
+            1
+        } else if operand.starts_with('#') {
+            2
+        } else if operand.starts_with('$') {
+            let digits = operand[1..].chars()
+                                     .take_while(|c| c.is_digit(16)).count();
+            if digits == 2 {2} else {3}
+        } else {
+            3
+        }
+    }
+
+    #[test]
+    pub fn test_vectors() {
+        let source = disassemble("test/nestest_direct.nes").unwrap();
+        let lines = bank_lines(&source);
+        // NMI at $C5AF, reset at $C000 and IRQ at $C5F4
+        let position = |line: &str| lines.iter().position(|l| *l == line)
+                                                .unwrap();
+        assert_eq!(position("reset:"), 0);
+        let after = |label: &str| lines[position(label) + 1]
+                                      .split_whitespace().collect::<Vec<_>>();
+        assert_eq!(after("nmi:"), ["pha", ";", "C5AF"]);
+        assert_eq!(after("irq:"), ["rti", ";", "C5F4"]);
+        let vectors: Vec<&str> = lines.iter().filter(|l| l.contains(".addr"))
+                                             .map(|l| l.trim()).collect();
+        assert_eq!(vectors, [".addr nmi", ".addr reset", ".addr irq"]);
+    }
+
+    #[test]
+    pub fn test_bank_bytes() {
+        let source = disassemble("test/nestest_direct.nes").unwrap();
+        let mut rom = Vec::new();
+        File::open("test/nestest_direct.nes").unwrap()
+                                             .read_to_end(&mut rom).unwrap();
+        let prg = &rom[0x10..0x4010];
+        // Every line has to stand for the bytes at its place in the bank
+        let mut offset = 0;
+        for line in bank_lines(&source) {
+            let mut parts = line.splitn(2, ';');
+            let text = parts.next().unwrap().trim();
+            let comment = parts.next().map(|comment| comment.trim());
+            if text.ends_with(':') {
+                continue;
+            }
+            if let Some(comment) = comment {
+                let address = format!("{:04X}", 0xC000 + offset);
+                assert!(comment.starts_with(&address), "{}", line);
+            }
+            if text.starts_with(".byte ") {
+                let bytes: Vec<u8> = text[".byte ".len()..].split(", ")
+                    .map(|b| u8::from_str_radix(&b[1..], 16).unwrap())
+                    .collect();
+                assert_eq!(&prg[offset..offset + bytes.len()], &bytes[..],
+                           "{}", line);
+                offset += bytes.len();
+            } else if text.starts_with(".addr ") {
+                offset += 2;
+            } else {
+                assert!(comment.is_some(), "{}", line);
+                offset += instruction_size(text);
+            }
+        }
+        assert_eq!(offset, prg.len());
+    }
+
+    #[test]
+    pub fn test_split_banks() {
+        // UxROM, the first bank is switched at $8000 and ends with bytes
+        // that would be vectors to $8000, the last one stays at $C000
+        let mut prg = vec![0xEA; 0x8000];
+        prg[0x3FFA..0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00,
+                                              0x80]);
+        // $C000: JMP $C000, $C003: RTI
+        prg[0x4000..0x4004].copy_from_slice(&[0x4C, 0x00, 0xC0, 0x40]);
+        prg[0x7FFA..].copy_from_slice(&[0x03, 0xC0, 0x00, 0xC0, 0x03, 0xC0]);
+        let path = env::temp_dir().join("warnes_test_uxrom.nes");
+        let mut rom = File::create(&path).unwrap();
+        rom.write_all(b"NES\x1a\x02\x00\x20\0\0\0\0\0\0\0\0\0").unwrap();
+        rom.write_all(&prg).unwrap();
+        let source = disassemble(&path).unwrap();
+        assert!(source.contains("B00: start = $8000, size = $4000"));
+        assert!(source.contains("B01: start = $C000, size = $4000"));
+        // No entry points in the switchable bank
+        let switched = scope_lines(&source, 0);
+        assert!(switched.iter().all(|line| line.trim().starts_with(".byte")));
+        let fixed = scope_lines(&source, 1);
+        let code: Vec<Vec<&str>> = fixed[..4].iter().map(|line| {
+            line.split_whitespace().collect()
+        }).collect();
+        assert_eq!(code, [vec!["reset:"], vec!["jmp", "reset", ";", "C000"],
+                          vec!["nmi:"], vec!["rti", ";", "C003"]]);
+        let vectors: Vec<&str> = fixed.iter().filter(|l| l.contains(".addr"))
+                                             .map(|l| l.trim()).collect();
+        assert_eq!(vectors, [".addr nmi", ".addr reset", ".addr nmi"]);
+    }
+}
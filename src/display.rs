@@ -0,0 +1,230 @@
+// nes
+use ppu::{SCANLINE_WIDTH, SCANLINE_COUNT};
+
+// Lines most NTSC TVs hid at the top and bottom
+const NTSC_OVERSCAN : usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aspect {
+    // Fills the window
+    Stretch,
+    Square,
+    // NTSC pixels are 8:7
+    Pixel,
+    // The whole frame fills a 4:3 TV
+    Tv,
+}
+
+impl Aspect {
+    // Width of a pixel relative to its height
+    fn pixel_ratio(&self) -> f32 {
+        match *self {
+            Aspect::Stretch | Aspect::Square => 1.0,
+            Aspect::Pixel => 8.0 / 7.0,
+            Aspect::Tv => 4.0 / 3.0 * SCANLINE_COUNT as f32 /
+                          SCANLINE_WIDTH as f32,
+        }
+    }
+}
+
+pub fn parse_aspect(name: &str) -> Result<Aspect, String> {
+    match name {
+        "stretch" => Ok(Aspect::Stretch),
+        "square"  => Ok(Aspect::Square),
+        "8:7"     => Ok(Aspect::Pixel),
+        "4:3"     => Ok(Aspect::Tv),
+        _         => err!("Invalid aspect ratio {}", name),
+    }
+}
+
+// Frame lines and columns hidden at each edge
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Overscan {
+    pub top         : usize,
+    pub bottom      : usize,
+    pub left        : usize,
+    pub right       : usize,
+}
+
+// TOP,BOTTOM,LEFT,RIGHT or ntsc
+pub fn parse_overscan(value: &str) -> Result<Overscan, String> {
+    if value == "ntsc" {
+        return Ok(Overscan {
+            top         : NTSC_OVERSCAN,
+            bottom      : NTSC_OVERSCAN,
+            left        : 0,
+            right       : 0,
+        });
+    }
+    let mut sides = Vec::new();
+    for side in value.split(',') {
+        sides.push(try_err!(side.parse(), "Invalid overscan {}", value));
+    }
+    if sides.len() != 4 || sides[0] + sides[1] >= SCANLINE_COUNT ||
+       sides[2] + sides[3] >= SCANLINE_WIDTH {
+        return err!("Invalid overscan {}", value);
+    }
+    Ok(
+        Overscan {
+            top         : sides[0],
+            bottom      : sides[1],
+            left        : sides[2],
+            right       : sides[3],
+        }
+    )
+}
+
+// A rectangle in pixels, frontends turn it into their own type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub x           : i32,
+    pub y           : i32,
+    pub width       : u32,
+    pub height      : u32,
+}
+
+// How the frame is placed in the window
+#[derive(Debug, Clone, Copy)]
+pub struct Display {
+    pub aspect          : Aspect,
+    // Only whole multiples of the frame height, with black bars around
+    pub integer_scale   : bool,
+    pub overscan        : Overscan,
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display {
+            aspect          : Aspect::Stretch,
+            integer_scale   : false,
+            overscan        : Overscan::default(),
+        }
+    }
+}
+
+impl Display {
+    // Frame pixels left after the overscan
+    fn visible(&self) -> (usize, usize) {
+        let overscan = &self.overscan;
+        (SCANLINE_WIDTH - overscan.left - overscan.right,
+         SCANLINE_COUNT - overscan.top - overscan.bottom)
+    }
+
+    pub fn window_size(&self, scale: u32) -> (u32, u32) {
+        let (width, height) = self.visible();
+        let width = width as f32 * self.aspect.pixel_ratio() * scale as f32;
+        (width.round() as u32, height as u32 * scale)
+    }
+
+    // Part of an image of the frame that is shown, the image can be a
+    // scaled or filtered version of the frame
+    pub fn source(&self, width: usize, height: usize) -> Area {
+        let overscan = &self.overscan;
+        let (scale_x, scale_y) = (width as f32 / SCANLINE_WIDTH as f32,
+                                  height as f32 / SCANLINE_COUNT as f32);
+        let x = |column: usize| (column as f32 * scale_x).round() as u32;
+        let y = |line: usize| (line as f32 * scale_y).round() as u32;
+        // Rounding both edges, not the sizes, keeps them on the same pixels
+        // the frame ones land on
+        let (left, top) = (x(overscan.left), y(overscan.top));
+        let right = x(SCANLINE_WIDTH - overscan.right);
+        let bottom = y(SCANLINE_COUNT - overscan.bottom);
+        Area {
+            x           : left as i32,
+            y           : top as i32,
+            width       : right - left,
+            height      : bottom - top,
+        }
+    }
+
+    // Where the frame goes in the window, centered, None fills it
+    pub fn target(&self, window: (u32, u32)) -> Option<Area> {
+        if self.aspect == Aspect::Stretch && !self.integer_scale {
+            return None;
+        }
+        let (width, height) = self.visible();
+        let width = width as f32 * self.aspect.pixel_ratio();
+        let height = height as f32;
+        let mut scale = (window.0 as f32 / width).min(window.1 as f32 / height);
+        if self.integer_scale {
+            scale = scale.floor().max(1.0);
+        }
+        let (width, height) = ((width * scale).round() as u32,
+                               (height * scale).round() as u32);
+        Some(Area {
+            x           : (window.0 as i32 - width as i32) / 2,
+            y           : (window.1 as i32 - height as i32) / 2,
+            width       : width,
+            height      : height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use display::*;
+    use ntsc::NTSC_WIDTH;
+
+    fn display(aspect: Aspect, integer_scale: bool, overscan: &str)
+               -> Display {
+        Display {
+            aspect          : aspect,
+            integer_scale   : integer_scale,
+            overscan        : parse_overscan(overscan).unwrap(),
+        }
+    }
+
+    #[test]
+    pub fn test_window_size() {
+        assert_eq!(display(Aspect::Square, false, "0,0,0,0").window_size(2),
+                   (512, 480));
+        // 256 * 8 / 7 * 2 is 585.14
+        assert_eq!(display(Aspect::Pixel, false, "0,0,0,0").window_size(2),
+                   (585, 480));
+        assert_eq!(display(Aspect::Tv, false, "0,0,0,0").window_size(2),
+                   (640, 480));
+        // The TV keeps its pixel shape when lines are hidden
+        assert_eq!(display(Aspect::Tv, false, "ntsc").window_size(2),
+                   (640, 448));
+    }
+
+    #[test]
+    pub fn test_target() {
+        assert_eq!(display(Aspect::Stretch, false, "ntsc").target((800, 600)),
+                   None);
+        // 2.5 times fits, letterboxed down to 2
+        let area = |x, y, width, height| Some(Area {x: x, y: y,
+                                                    width: width,
+                                                    height: height});
+        assert_eq!(display(Aspect::Square, true, "0,0,0,0").target((800, 600)),
+                   area(144, 60, 512, 480));
+        assert_eq!(display(Aspect::Stretch, true, "ntsc").target((800, 600)),
+                   area(144, 76, 512, 448));
+        // Never below 1, cropped by the window
+        assert_eq!(display(Aspect::Square, true, "0,0,0,0").target((200, 200)),
+                   area(-28, -20, 256, 240));
+        // Without the integer scale the aspect fills one side
+        assert_eq!(display(Aspect::Tv, false, "0,0,0,0").target((800, 480)),
+                   area(80, 0, 640, 480));
+    }
+
+    #[test]
+    pub fn test_source_overscan() {
+        let overscan = display(Aspect::Stretch, false, "8,8,8,8");
+        assert_eq!(overscan.source(256, 240), Area {x: 8, y: 8, width: 240,
+                                                    height: 224});
+        assert_eq!(overscan.source(512, 480), Area {x: 16, y: 16,
+                                                    width: 480,
+                                                    height: 448});
+        // 8 of 256 columns are 18.8 NTSC pixels, rounded on both sides
+        let ntsc = overscan.source(NTSC_WIDTH, 240);
+        assert_eq!(ntsc, Area {x: 19, y: 8, width: 564, height: 224});
+        let exact = NTSC_WIDTH as f32 * 240.0 / 256.0;
+        assert!((ntsc.width as f32 - exact).abs() < 1.0);
+        // Odd sides on the NTSC width
+        let ntsc = display(Aspect::Stretch, false, "0,0,3,5")
+                       .source(NTSC_WIDTH, 240);
+        assert_eq!(ntsc.x, 7);
+        assert_eq!(ntsc.x as u32 + ntsc.width, NTSC_WIDTH as u32 - 12);
+    }
+}
@@ -24,4 +24,69 @@ pub enum IoState {
 pub enum Interrupt {
     NMI,
     IRQ,
+    Reset,
+}
+
+// APU devices that can hold the IRQ line low, mappers are asked directly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+}
+
+// Contents of the internal RAM at power on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamInit {
+    Zeros,
+    Ones,
+    Random(u64),
+    // Four bytes of $00 and four of $FF, seen on many consoles
+    Pattern,
+}
+
+// TV system, it sets the clock ratios and frame length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    // PPU dots per CPU cycle as a fraction
+    pub fn ppu_ratio(&self) -> (u32, u32) {
+        match *self {
+            Region::Pal => (16, 5),
+            Region::Ntsc | Region::Dendy => (3, 1),
+        }
+    }
+
+    pub fn scanlines(&self) -> usize {
+        match *self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Only the NTSC PPU skips a dot on odd frames
+    pub fn skips_odd_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    // CPU cycles per second
+    pub fn cpu_clock(&self) -> u32 {
+        match *self {
+            Region::Ntsc  => 1789773,
+            Region::Pal   => 1662607,
+            Region::Dendy => 1773448,
+        }
+    }
+
+    // Scanline where the VBlank flag is set
+    pub fn vblank_line(&self) -> usize {
+        match *self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
 }
@@ -0,0 +1,98 @@
+// std
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::Path;
+
+const REGISTER_NAMES : [&'static str; 8] = [
+    "PPUCTRL", "PPUMASK", "PPUSTATUS", "OAMADDR",
+    "OAMDATA", "PPUSCROLL", "PPUADDR", "PPUDATA",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    // CPU write to $2000-$2007 or $4014
+    Register(u16),
+    // The mapper started holding the IRQ line
+    MapperIrq,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub scanline    : usize,
+    pub dot         : usize,
+    pub kind        : EventKind,
+    pub value       : u8,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            EventKind::Register(0x4014) => "OAMDMA",
+            EventKind::Register(address) =>
+                REGISTER_NAMES[(address & 0x7) as usize],
+            EventKind::MapperIrq => "IRQ",
+        }
+    }
+
+    // Dots past the visible part of the scanline
+    pub fn in_hblank(&self) -> bool {
+        self.dot == 0 || self.dot > 256
+    }
+}
+
+// Events of the frame being run and of the last complete one
+pub struct EventLog {
+    current         : Vec<Event>,
+    frame           : Vec<Event>,
+    frame_number    : u64,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog {
+            current         : Vec::new(),
+            frame           : Vec::new(),
+            frame_number    : 0,
+        }
+    }
+
+    pub fn record(&mut self, event: Event) {
+        self.current.push(event);
+    }
+
+    // Keeps the events once the PPU moved to another frame
+    pub fn end_frame(&mut self, frame_number: u64) {
+        if frame_number != self.frame_number {
+            self.frame = mem::replace(&mut self.current, Vec::new());
+            self.frame_number = frame_number;
+        }
+    }
+
+    pub fn frame(&self) -> &[Event] {
+        &self.frame
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let file = try_err!(File::create(path), "Couldn't create events file");
+        let mut output = BufWriter::new(file);
+        for event in &self.frame {
+            let target = match event.kind {
+                EventKind::Register(address) =>
+                    format!("${:04X} = {:02X}", address, event.value),
+                EventKind::MapperIrq => "mapper".to_string(),
+            };
+            try_err!(writeln!(output, "{:3},{:3}  {:<9} {:<10}{}",
+                              event.scanline, event.dot, event.name(), target,
+                              if event.in_hblank() {" HBlank"} else {""}),
+                     "Couldn't write events file");
+        }
+        Ok(())
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        EventLog::new()
+    }
+}
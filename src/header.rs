@@ -4,6 +4,7 @@ use std::fs::File;
 use std::path::Path;
 
 use mapper::*;
+use enums::Region;
 
 const INES_SIGNATURE        : [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const INES_HEADER_SIZE      : usize = 0x10;
@@ -22,8 +23,11 @@ const FLAGS_BATTERY         : u8 = 0x02;
 const FLAGS_TRAINER         : u8 = 0x04;
 const FLAGS_4SCREEN         : u8 = 0x08;
 
+const NES2_TIMING_MASK      : u8 = 0x03;
+
 pub struct Header {
     rom_file     : File,
+    file_header  : [u8; INES_HEADER_SIZE],
     mapper       : u16,
     flags        : u8,
     prg_rom_size : usize,
@@ -32,6 +36,7 @@ pub struct Header {
     chr_rom_size : usize,
     chr_ram_size : usize,
     chr_bat_size : usize,
+    region       : Option<Region>,
 }
 
 impl Header {
@@ -62,13 +67,25 @@ impl Header {
         if chr_rom_size == 0 {
             chr_ram_size = INES_CHR_RAM_SIZE;
         }
-        if flags & NES2_SIGN_MASK == NES2_SIGNATURE {
-            println!("Warning: iNES 2.0 header detected and not parsed");
-            // TODO: NES 2.0 parsing
-        }
+        // There's no game database, only NES 2.0 headers tell the region.
+        // The iNES byte 9 TV bit is ignored, almost no dump sets it and
+        // some set it wrong.
+        let region = if flags & NES2_SIGN_MASK == NES2_SIGNATURE {
+            println!("Warning: NES 2.0 header, only the timing is parsed");
+            // TODO: NES 2.0 submapper, ROM and RAM sizes
+            match file_header[12] & NES2_TIMING_MASK {
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                // Multi region games run fine as NTSC
+                _ => Some(Region::Ntsc),
+            }
+        } else {
+            None
+        };
         Ok(
             Header {
                 rom_file     : rom,
+                file_header  : file_header,
                 mapper       : mapper,
                 flags        : flags,
                 prg_rom_size : prg_rom_size,
@@ -77,6 +94,7 @@ impl Header {
                 chr_rom_size : chr_rom_size,
                 chr_ram_size : chr_ram_size,
                 chr_bat_size : chr_bat_size,
+                region       : region,
             }
         )
     }
@@ -86,11 +104,37 @@ impl Header {
         match self.mapper {
             0 => Ok(Nrom::new_boxed(mem)),
             3 => Ok(Cnrom::new_boxed(mem)),
+            // Its nametables can come from CHR ROM, there's no RAM version
+            68 if mem.chr_rom.is_empty() => err!("Sunsoft-4 needs CHR ROM"),
+            68 => Ok(Sunsoft4::new_boxed(mem)),
             225 => Ok(Pirate225::new_boxed(mem)),
             _ => err!("Unrecognized Mapper {}", self.mapper)
         }
     }
 
+    pub fn mapper_number(&self) -> u16 {
+        self.mapper
+    }
+
+    // None without a NES 2.0 header, --region is the way to set it then
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    pub fn file_header(&self) -> &[u8] {
+        &self.file_header
+    }
+
+    pub fn get_trainer(&mut self) -> Result<Vec<u8>, Error> {
+        let mut trainer = Vec::new();
+        if is_flag_set!(self.flags, FLAGS_TRAINER) {
+            trainer.resize(INES_TRAINER_SIZE, 0);
+            self.rom_file.seek(SeekFrom::Start(INES_HEADER_SIZE as u64))?;
+            self.rom_file.read_exact(&mut trainer)?;
+        }
+        Ok(trainer)
+    }
+
     pub fn get_game_memory(&mut self) -> Result<GameMemory, Error> {
         let mut prg_rom = vec![0u8; self.prg_rom_size].into_boxed_slice();
         let mut chr_rom = vec![0u8; self.chr_rom_size].into_boxed_slice();
@@ -101,6 +145,13 @@ impl Header {
         self.rom_file.seek(SeekFrom::Start(offset as u64))?;
         self.rom_file.read_exact(&mut *prg_rom)?;
         self.rom_file.read_exact(&mut *chr_rom)?;
+        let mirroring = if is_flag_set!(self.flags, FLAGS_4SCREEN) {
+            Mirroring::FourScreen
+        } else if is_flag_set!(self.flags, FLAGS_VMIRROR) {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
         Ok(
             GameMemory {
                 prg_rom : prg_rom,
@@ -109,8 +160,9 @@ impl Header {
                 chr_rom : chr_rom,
                 chr_ram : vec![0; self.chr_ram_size].into_boxed_slice(),
                 chr_bat : vec![0; self.chr_bat_size].into_boxed_slice(),
-                vmirror : is_flag_set!(self.flags, FLAGS_VMIRROR),
-                screen4 : is_flag_set!(self.flags, FLAGS_4SCREEN),
+                nt_ram  : vec![0; mirroring.cartridge_vram()]
+                              .into_boxed_slice(),
+                nametables : mirroring.nametables(),
             }
         )
     }
@@ -1,4 +1,7 @@
+use backend::Hotkey;
+// SDL2
 use sdl2::EventPump;
+use sdl2::event::Event;
 use sdl2::keyboard::{Scancode as S, KeyboardState};
 
 // A, B, Select, Start, Up, Down, Left, Right
@@ -7,6 +10,27 @@ const PLAYER_KEYS : [[S; 8]; 2] = [
     [S::K, S::J, S::L, S::Return, S::W, S::S, S::A, S::D],
 ];
 
+const HOTKEYS : [(S, Hotkey); 8] = [
+    (S::F1, Hotkey::Background),
+    (S::F2, Hotkey::Sprites),
+    (S::F3, Hotkey::PreviousSprite),
+    (S::F4, Hotkey::NextSprite),
+    (S::F5, Hotkey::HideSprite),
+    (S::F6, Hotkey::ShowAll),
+    (S::F7, Hotkey::ViewerPalette),
+    (S::F8, Hotkey::Scaler),
+];
+
+// Hotkeys pressed since the last call, held keys don't repeat
+pub fn get_hotkeys(event_pump: &mut EventPump) -> Vec<Hotkey> {
+    event_pump.poll_iter().filter_map(|event| match event {
+        Event::KeyDown { scancode: Some(scancode), repeat: false, .. } =>
+            HOTKEYS.iter().find(|&&(key, _)| key == scancode)
+                          .map(|&(_, hotkey)| hotkey),
+        _ => None,
+    }).collect()
+}
+
 // Returns true if user wants to exit, sets controller keys accordingly
 pub fn get_keys(event_pump: &mut EventPump, keys: &mut [[u8; 8]; 2]) -> bool {
     event_pump.pump_events();
@@ -0,0 +1,40 @@
+// The emulator core and the frontend traits, the binary is one user of it
+// and test harnesses can be others without linking SDL
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+
+#[macro_use]
+mod macros;
+pub mod cpu;
+pub mod mem;
+pub mod ppu;
+pub mod apu;
+pub mod scroll;
+pub mod nes;
+pub mod controller;
+pub mod header;
+pub mod loadstore;
+pub mod utils;
+pub mod mapper;
+pub mod debug;
+pub mod disasm;
+pub mod trace;
+pub mod cdl;
+pub mod enums;
+pub mod events;
+pub mod backend;
+pub mod ntsc;
+pub mod palette;
+pub mod scale;
+pub mod display;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "sdl")]
+pub mod render;
+#[cfg(feature = "sdl")]
+pub mod input;
+#[cfg(feature = "sdl")]
+pub mod viewer;
+
+pub use nes::Nes;
+pub use backend::{Backend, Headless};
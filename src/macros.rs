@@ -1,7 +1,10 @@
+// Exported for the binary, the rest stay inside the crate
+#[macro_export]
 macro_rules! err {
     ($($f:expr),*) => (Err(format!($($f),*)))
 }
 
+#[macro_export]
 macro_rules! try_err {
     ($e:expr, $($f:expr),*) =>
         (match $e {Ok(a) => a, Err(_) => return err!($($f),*)})
@@ -51,10 +54,6 @@ macro_rules! W8 {
     ($val:expr) => (W($val.0 as u8))
 }
 
-macro_rules! get_bit {
-        ($flags:expr, $flag_bit:expr) => ($flags & $flag_bit;);
-}
-
 macro_rules! set_low_byte {
     ($val:expr, $byte:expr) => ($val = $val & W(0xFF00) | W16!(W8!($byte)))
 }
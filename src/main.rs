@@ -1,100 +1,246 @@
-extern crate sdl2;
-extern crate time;
-
 #[macro_use]
-mod macros;
-mod cpu;
-mod mem;
-mod ppu;
-mod scroll;
-mod nes;
-mod controller;
-mod header;
-mod loadstore;
-mod utils;
-mod mapper;
-mod debug;
-mod enums;
-mod render;
-mod input;
+extern crate warnes;
 
 // std
 use std::env;
-use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 // Nes
-use nes::Nes;
-// input
-use input::get_keys;
-// Time
-use time::PreciseTime;
-// Render
-use render::render_frame;
-// SDL2
-use sdl2::render::Renderer;
-use sdl2::EventPump;
+use warnes::Nes;
+use warnes::enums::{RamInit, Region};
+use warnes::{debug, disasm};
+// Trace
+use warnes::trace::{Trace, parse_range};
+// Frontend
+use warnes::backend::{Backend, Headless, Hotkey};
+#[cfg(feature = "sdl")]
+use warnes::sdl::{self, SdlBackend};
 
-const WIDTH  : u32 = 256;
-const HEIGHT : u32 = 240;
+// SDL unless asked to run headless, frontend arguments go to SDL
+#[cfg(feature = "sdl")]
+fn create_backend(headless: bool, args: &[&str])
+                  -> Result<Box<dyn Backend>, String> {
+    if !headless {
+        return Ok(Box::new(SdlBackend::new(args)?));
+    }
+    match args.first() {
+        Some(arg) => err!("Invalid parameter {} when headless", arg),
+        None => Ok(Box::new(Headless)),
+    }
+}
 
-fn sdl() -> Result<(Renderer<'static>, EventPump), Box<Error>> {
-    let context = sdl2::init()?;
-    let window = context.video()?.window("Warnes", WIDTH, HEIGHT)
-                                 .position_centered().resizable().build()?;
-    let renderer = window.renderer().present_vsync().build()?;
-    Ok((renderer, context.event_pump()?))
+#[cfg(not(feature = "sdl"))]
+fn create_backend(_headless: bool, args: &[&str])
+                  -> Result<Box<dyn Backend>, String> {
+    match args.first() {
+        Some(arg) => err!("Invalid parameter {}", arg),
+        None => Ok(Box::new(Headless)),
+    }
+}
+
+fn parse_ram_init(value: &str) -> Result<RamInit, String> {
+    let mut parts = value.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("zeros"), None)   => Ok(RamInit::Zeros),
+        (Some("ones"), None)    => Ok(RamInit::Ones),
+        (Some("pattern"), None) => Ok(RamInit::Pattern),
+        (Some("random"), None)  => {
+            let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+                                        .map(|d| d.as_secs()).unwrap_or(0);
+            println!("RAM seed: {}", seed);
+            Ok(RamInit::Random(seed))
+        },
+        (Some("random"), Some(seed)) =>
+            Ok(RamInit::Random(try_err!(seed.parse(), "Invalid seed {}", seed))),
+        _ => err!("Invalid RAM initialization {}", value),
+    }
+}
+
+fn parse_region(value: &str) -> Result<Region, String> {
+    match value {
+        "ntsc"  => Ok(Region::Ntsc),
+        "pal"   => Ok(Region::Pal),
+        "dendy" => Ok(Region::Dendy),
+        _       => err!("Invalid region {}", value),
+    }
+}
+
+// Applies a debug hotkey, selected is the OAM entry F5 toggles
+fn debug_hotkey(nes: &mut Nes, hotkey: Hotkey, selected: &mut usize) {
+    let mut layers = nes.ppu().debug_layers();
+    match hotkey {
+        // Handled by the frontend
+        Hotkey::ViewerPalette | Hotkey::Scaler => return,
+        Hotkey::Background => {
+            layers.hide_background = !layers.hide_background;
+            println!("Background {}", if layers.hide_background {"hidden"}
+                                      else {"shown"});
+        },
+        Hotkey::Sprites => {
+            layers.hide_sprites = !layers.hide_sprites;
+            println!("Sprites {}", if layers.hide_sprites {"hidden"}
+                                   else {"shown"});
+        },
+        Hotkey::PreviousSprite | Hotkey::NextSprite => {
+            let step = if hotkey == Hotkey::NextSprite {1} else {63};
+            *selected = (*selected + step) % 64;
+            println!("Selected sprite {}", selected);
+        },
+        Hotkey::HideSprite => {
+            layers.hidden_sprites ^= 1 << *selected;
+            println!("Sprite {} {}", selected,
+                     if layers.sprite_hidden(*selected) {"hidden"}
+                     else {"shown"});
+        },
+        Hotkey::ShowAll => {
+            layers = Default::default();
+            println!("All layers shown");
+        },
+    }
+    nes.set_debug_layers(layers);
 }
 
 fn warnes() -> Result<(), String> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 || args.len() > 3 {
+    if args.len() == 3 && args[1] == "disasm" {
+        print!("{}", disasm::disassemble(&args[2])?);
+        return Ok(());
+    }
+    if args.len() < 2 {
        return err!("Invalid parameter count");
     }
-    let (mut renderer, mut event_pump) = try_err!(sdl(), "Couldn't init SDL");
-    let mut nes = Nes::new(&args[1])?;
-    if args.len() == 3 {
-        if args[2] == "debug" {
-            debug::run(&mut nes);
+    let mut debug = false;
+    let mut ram = RamInit::Pattern;
+    let mut trace_file = None;
+    let mut trace_ranges = Vec::new();
+    let mut cdl_file = None;
+    let mut events_file = None;
+    let mut region = None;
+    let mut sprite_limit = true;
+    let mut headless = false;
+    let mut frames = None;
+    let mut frontend_args = Vec::new();
+    for arg in &args[2..] {
+        if arg == "debug" {
+            debug = true;
+        } else if arg == "--no-sprite-limit" {
+            sprite_limit = false;
+        } else if arg == "--headless" {
+            headless = true;
+        } else if arg.starts_with("--frames=") {
+            let count = &arg["--frames=".len()..];
+            frames = Some(try_err!(count.parse::<u64>(),
+                                   "Invalid frame count {}", count));
+        } else if arg.starts_with("--ram=") {
+            ram = parse_ram_init(&arg["--ram=".len()..])?;
+        } else if arg.starts_with("--trace=") {
+            trace_file = Some(&arg["--trace=".len()..]);
+        } else if arg.starts_with("--region=") {
+            region = Some(parse_region(&arg["--region=".len()..])?);
+        } else if arg.starts_with("--events=") {
+            events_file = Some(&arg["--events=".len()..]);
+        } else if arg.starts_with("--cdl=") {
+            cdl_file = Some(Path::new(&arg["--cdl=".len()..]));
+        } else if arg.starts_with("--trace-pc=") {
+            trace_ranges.push(parse_range(&arg["--trace-pc=".len()..])?);
         } else {
-            return err!("Invalid parameter {}", args[2]);
+            frontend_args.push(&arg[..]);
         }
+    }
+    let mut backend = create_backend(headless, &frontend_args)?;
+    let mut nes = Nes::new(&args[1])?;
+    if let Some(region) = region {
+        nes.set_region(region);
+    }
+    println!("Region: {:?}", nes.region());
+    nes.set_sprite_limit(sprite_limit);
+    nes.power_on(ram);
+    if let Some(path) = trace_file {
+        nes.set_trace(Some(Trace::to_file(path, trace_ranges)?));
+    } else if !trace_ranges.is_empty() {
+        return err!("PC ranges given without a trace file");
+    }
+    if let Some(path) = cdl_file {
+        // An existing log of the same ROM keeps growing
+        nes.start_cdl(if path.exists() {Some(path)} else {None})?;
+    }
+    if events_file.is_some() {
+        nes.start_events();
+    }
+    backend.start(&mut nes);
+    if debug {
+        debug::run(&mut nes);
     } else {
         let mut keys = [[0u8; 8]; 2];
+        let mut hotkeys = Vec::new();
+        let mut selected = 0;
         let (mut frame, mut last_frame) = (0u64, 0u64);
-        let mut time = PreciseTime::now();
-        nes.reset();
+        let mut time = Instant::now();
         'nes: loop {
-            if time.to(PreciseTime::now()) > time::Duration::seconds(1) {
-                time = PreciseTime::now();
+            if time.elapsed() > Duration::from_secs(1) {
+                time = Instant::now();
                 println!("FPS: {}", frame - last_frame);
                 last_frame = frame;
             }
-            {
-                let (number, data) = nes.ppu().frame_data();
-                if frame != number {
+            if nes.ppu().frame_data().0 != frame {
+                {
+                    let (number, data) = nes.ppu().frame_data();
                     frame = number;
-                    render_frame(&mut renderer, data);
-                    if get_keys(&mut event_pump, &mut keys) {
-                        break 'nes;
-                    }
+                    // The frame shown was drawn during the previous one
+                    let phase = nes.ppu().frame_phase();
+                    backend.frame(data, number - 1, phase)?;
+                }
+                let samples = nes.audio_samples();
+                backend.queue(samples)?;
+                samples.clear();
+                if backend.poll(&mut keys, &mut hotkeys) ||
+                   frames.map_or(false, |frames| frame >= frames) {
+                    break 'nes;
                 }
             }
+            for hotkey in hotkeys.drain(..) {
+                debug_hotkey(&mut nes, hotkey, &mut selected);
+            }
+            if nes.cpu().jammed() {
+                let execution = nes.cpu().execution();
+                println!("CPU jammed by opcode {:02X} at {:04X}",
+                         execution.operation.opcode, execution.pc.0);
+                break 'nes;
+            }
             nes.set_keys(&keys);
             // Does a full cpu cycle (includes 3 ppu cycles)
             nes.cycle();
+            backend.cycle(&nes)?;
         }
     }
+    nes.set_trace(None);
+    if let Some(path) = cdl_file {
+        nes.save_cdl(path)?;
+    }
+    // The last complete frame
+    if let Some(path) = events_file {
+        nes.save_events(path)?;
+    }
     Ok(())
 }
 
 fn main() {
     match warnes() {
         Ok(()) => {
-            println!("Exiting Warnes.");
+            // Keep stdout clean for the disassembler output
+            eprintln!("Exiting Warnes.");
             std::process::exit(0);
         },
         Err(err) => {
             println!("Error: {}", err);
-            println!("Usage: warnes ROM_FILE [debug]");
+            println!("Usage: warnes ROM_FILE [debug] \
+                      [--ram=zeros|ones|pattern|random[:SEED]] \
+                      [--trace=FILE [--trace-pc=START-END]...] [--cdl=FILE] \
+                      [--region=ntsc|pal|dendy] [--no-sprite-limit] \
+                      [--events=FILE] [--headless] [--frames=COUNT]");
+            #[cfg(feature = "sdl")]
+            println!("       {}", sdl::OPTIONS);
+            println!("       warnes disasm ROM_FILE");
             std::process::exit(1);
         },
     };
@@ -1,14 +1,45 @@
 use std::num::Wrapping as W;
 
-const VRAM_SIZE : usize = 0x800;
 const NT_SIZE   : usize = 0x400;
 
-pub fn hmirror(address: usize) -> usize {
-    ((address >> 1) & NT_SIZE) + (address & NT_SIZE - 1)
+// Memory behind one of the four 1KiB nametables
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nametable {
+    // Page of the 2KiB console VRAM
+    Vram(usize),
+    // Page of the extra VRAM on the cartridge
+    Cartridge(usize),
+    // Offset in CHR ROM, read only
+    ChrRom(usize),
 }
 
-pub fn vmirror(address: usize) -> usize {
-    address & VRAM_SIZE - 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenA,
+    SingleScreenB,
+    // The cartridge adds 2KiB of VRAM for the last two nametables
+    FourScreen,
+}
+
+impl Mirroring {
+    pub fn nametables(&self) -> [Nametable; 4] {
+        use self::Nametable::*;
+        match *self {
+            Mirroring::Horizontal    => [Vram(0), Vram(0), Vram(1), Vram(1)],
+            Mirroring::Vertical      => [Vram(0), Vram(1), Vram(0), Vram(1)],
+            Mirroring::SingleScreenA => [Vram(0), Vram(0), Vram(0), Vram(0)],
+            Mirroring::SingleScreenB => [Vram(1), Vram(1), Vram(1), Vram(1)],
+            Mirroring::FourScreen    =>
+                [Vram(0), Vram(1), Cartridge(0), Cartridge(1)],
+        }
+    }
+
+    // Size of the VRAM the cartridge needs
+    pub fn cartridge_vram(&self) -> usize {
+        if *self == Mirroring::FourScreen {2 * NT_SIZE} else {0}
+    }
 }
 
 pub struct GameMemory {
@@ -18,15 +49,39 @@ pub struct GameMemory {
     pub chr_rom     : Box<[u8]>,
     pub chr_ram     : Box<[u8]>,
     pub chr_bat     : Box<[u8]>,
-    pub vmirror     : bool,
-    pub screen4     : bool,
+    pub nt_ram      : Box<[u8]>,
+    // Mappers can change them at any time
+    pub nametables  : [Nametable; 4],
 }
 
 impl GameMemory {
-    fn chr_load(&mut self, vram: &mut[u8], addr: W<u16>, bank: usize) -> u8 {
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.nametables = mirroring.nametables();
+    }
+
+    fn nametable_load(&self, vram: &[u8], addr: usize) -> u8 {
+        let offset = addr & (NT_SIZE - 1);
+        match self.nametables[(addr >> 10) & 0x3] {
+            Nametable::Vram(page)      => vram[page * NT_SIZE + offset],
+            Nametable::Cartridge(page) => self.nt_ram[page * NT_SIZE + offset],
+            Nametable::ChrRom(bank)    => self.chr_rom[bank + offset],
+        }
+    }
+
+    fn nametable_store(&mut self, vram: &mut [u8], addr: usize, value: u8) {
+        let offset = addr & (NT_SIZE - 1);
+        match self.nametables[(addr >> 10) & 0x3] {
+            Nametable::Vram(page)      => vram[page * NT_SIZE + offset] = value,
+            Nametable::Cartridge(page) =>
+                self.nt_ram[page * NT_SIZE + offset] = value,
+            Nametable::ChrRom(_)       => (),
+        }
+    }
+
+    fn chr_load(&self, vram: &[u8], addr: W<u16>, bank: usize) -> u8 {
         let addr = addr.0 as usize;
         if addr >= 0x2000 {
-            vram[if self.vmirror {vmirror(addr)} else {hmirror(addr)}]
+            self.nametable_load(vram, addr)
         } else {
             if self.chr_ram.len() > 0 {
                 self.chr_ram[bank + addr]
@@ -39,17 +94,43 @@ impl GameMemory {
     fn chr_store(&mut self, vram: &mut[u8], addr: W<u16>, value: u8) {
         let addr = addr.0 as usize;
         if addr >= 0x2000 {
-            vram[if self.vmirror {vmirror(addr)} else {hmirror(addr)}] = value;
+            self.nametable_store(vram, addr, value);
         } else if self.chr_ram.len() > 0 {
             self.chr_ram[addr] = value;
         }
     }
 
-    fn prg_load(&mut self, addr: W<u16>, bank: usize) -> u8 {
+    fn prg_load(&self, addr: W<u16>, bank: usize) -> u8 {
         // Emulate mirroring
         let mask = self.prg_rom.len() - 1;
         self.prg_rom[bank + (addr.0 as usize & mask)]
     }
+
+    fn prg_offset(&self, addr: W<u16>, bank: usize) -> Option<usize> {
+        if addr >= W(0x8000) {
+            Some(bank + (addr.0 as usize & (self.prg_rom.len() - 1)))
+        } else {
+            None
+        }
+    }
+
+    fn chr_offset(&self, addr: W<u16>, bank: usize) -> Option<usize> {
+        let addr = addr.0 as usize;
+        if addr >= 0x2000 {
+            match self.nametables[(addr >> 10) & 0x3] {
+                Nametable::ChrRom(bank) => Some(bank + (addr & (NT_SIZE - 1))),
+                _ => None,
+            }
+        } else if self.chr_ram.len() == 0 {
+            Some(bank + addr)
+        } else {
+            None
+        }
+    }
+
+    fn rom_sizes(&self) -> (usize, usize) {
+        (self.prg_rom.len(), self.chr_rom.len())
+    }
 }
 
 pub trait Mapper {
@@ -57,6 +138,17 @@ pub trait Mapper {
     fn chr_store(&mut self, vram: &mut[u8], address: W<u16>, value: u8);
     fn prg_load(&mut self, address: W<u16>) -> u8;
     fn prg_store(&mut self, address: W<u16>, value: u8);
+    // Reads without side effects, for debugging tools
+    fn chr_peek(&self, vram: &[u8], address: W<u16>) -> u8;
+    fn prg_peek(&self, address: W<u16>) -> u8;
+    // Offsets in PRG and CHR ROM currently mapped, None for RAM
+    fn prg_offset(&self, address: W<u16>) -> Option<usize>;
+    fn chr_offset(&self, address: W<u16>) -> Option<usize>;
+    fn rom_sizes(&self) -> (usize, usize);
+    // True while the mapper holds the IRQ line
+    fn irq(&self) -> bool;
+    fn power_on(&mut self);
+    fn soft_reset(&mut self);
 }
 
 pub struct Nrom(GameMemory);
@@ -81,6 +173,34 @@ impl Mapper for Nrom {
     }
 
     fn prg_store(&mut self, _: W<u16>, _: u8) {}
+
+    fn chr_peek(&self, vram: &[u8], address: W<u16>) -> u8 {
+        self.0.chr_load(vram, address, 0)
+    }
+
+    fn prg_peek(&self, address: W<u16>) -> u8 {
+        self.0.prg_load(address, 0)
+    }
+
+    fn prg_offset(&self, address: W<u16>) -> Option<usize> {
+        self.0.prg_offset(address, 0)
+    }
+
+    fn chr_offset(&self, address: W<u16>) -> Option<usize> {
+        self.0.chr_offset(address, 0)
+    }
+
+    fn rom_sizes(&self) -> (usize, usize) {
+        self.0.rom_sizes()
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn power_on(&mut self) {}
+
+    fn soft_reset(&mut self) {}
 }
 
 pub struct Cnrom {
@@ -112,6 +232,37 @@ impl Mapper for Cnrom {
             self.bank = (value as usize & 0x3) * 0x2000;
         }
     }
+
+    fn chr_peek(&self, vram: &[u8], address: W<u16>) -> u8 {
+        self.mem.chr_load(vram, address, self.bank)
+    }
+
+    fn prg_peek(&self, address: W<u16>) -> u8 {
+        self.mem.prg_load(address, 0)
+    }
+
+    fn prg_offset(&self, address: W<u16>) -> Option<usize> {
+        self.mem.prg_offset(address, 0)
+    }
+
+    fn chr_offset(&self, address: W<u16>) -> Option<usize> {
+        self.mem.chr_offset(address, self.bank)
+    }
+
+    fn rom_sizes(&self) -> (usize, usize) {
+        self.mem.rom_sizes()
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn power_on(&mut self) {
+        self.bank = 0;
+    }
+
+    // The bank register isn't connected to reset
+    fn soft_reset(&mut self) {}
 }
 
 pub struct Pirate225 {
@@ -137,9 +288,7 @@ impl Mapper for Pirate225 {
     }
 
     fn prg_load(&mut self, address: W<u16>) -> u8 {
-        // Emulate mirroring
-        let addr = (address.0 as usize) & (0x7FFF >> self.prg_small);
-        self.mem.prg_rom[self.prg_bank + addr]
+        self.prg_peek(address)
     }
 
     fn prg_store(&mut self, address: W<u16>, _: u8) {
@@ -149,7 +298,308 @@ impl Mapper for Pirate225 {
             self.prg_small = (addr & 0x1000) >> 12;
             self.chr_bank = (addr & 0x3F) << 13;
             self.prg_bank = ((addr >> 6) & 0x3F & !(1 - self.prg_small)) << 14;
-            self.mem.vmirror = addr & 0x2000 == 0;
+            self.mem.set_mirroring(if addr & 0x2000 == 0 {Mirroring::Vertical}
+                                   else {Mirroring::Horizontal});
         }
     }
+
+    fn chr_peek(&self, vram: &[u8], address: W<u16>) -> u8 {
+        self.mem.chr_load(vram, address, self.chr_bank)
+    }
+
+    fn prg_peek(&self, address: W<u16>) -> u8 {
+        // Emulate mirroring
+        let addr = (address.0 as usize) & (0x7FFF >> self.prg_small);
+        self.mem.prg_rom[self.prg_bank + addr]
+    }
+
+    fn prg_offset(&self, address: W<u16>) -> Option<usize> {
+        if address >= W(0x8000) {
+            let addr = (address.0 as usize) & (0x7FFF >> self.prg_small);
+            Some(self.prg_bank + addr)
+        } else {
+            None
+        }
+    }
+
+    fn chr_offset(&self, address: W<u16>) -> Option<usize> {
+        self.mem.chr_offset(address, self.chr_bank)
+    }
+
+    fn rom_sizes(&self) -> (usize, usize) {
+        self.mem.rom_sizes()
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn power_on(&mut self) {
+        self.soft_reset();
+    }
+
+    // Reset goes back to the multicart menu
+    fn soft_reset(&mut self) {
+        self.prg_store(W(0x8000), 0);
+    }
+}
+
+pub struct Sunsoft4 {
+    mem: GameMemory,
+    // Four 2KiB CHR banks
+    chr_banks: [usize; 4],
+    // CHR ROM offsets used as nametables instead of VRAM
+    nt_banks: [usize; 2],
+    mirroring: Mirroring,
+    chr_nametables: bool,
+    prg_bank: usize,
+    prg_ram: bool,
+}
+
+impl Sunsoft4 {
+    pub fn new_boxed(mem: GameMemory) -> Box<dyn Mapper> {
+        let mut mapper = Sunsoft4 {
+            mem: mem,
+            chr_banks: [0; 4],
+            nt_banks: [0; 2],
+            mirroring: Mirroring::Vertical,
+            chr_nametables: false,
+            prg_bank: 0,
+            prg_ram: false,
+        };
+        mapper.power_on();
+        Box::new(mapper)
+    }
+
+    // The two VRAM pages mirroring selects are replaced by the CHR ROM ones
+    fn update_nametables(&mut self) {
+        let mut nametables = self.mirroring.nametables();
+        if self.chr_nametables {
+            for nametable in nametables.iter_mut() {
+                if let Nametable::Vram(page) = *nametable {
+                    *nametable = Nametable::ChrRom(self.nt_banks[page]);
+                }
+            }
+        }
+        self.mem.nametables = nametables;
+    }
+
+    fn chr_address(&self, address: usize) -> usize {
+        (self.chr_banks[address >> 11] * 0x800 + (address & 0x7FF)) %
+            self.mem.chr_rom.len()
+    }
+}
+
+impl Mapper for Sunsoft4 {
+    fn chr_load(&mut self, vram: &mut[u8], address: W<u16>) -> u8 {
+        self.chr_peek(vram, address)
+    }
+
+    fn chr_store(&mut self, vram: &mut[u8], address: W<u16>, value: u8) {
+        self.mem.chr_store(vram, address, value);
+    }
+
+    fn prg_load(&mut self, address: W<u16>) -> u8 {
+        self.prg_peek(address)
+    }
+
+    fn prg_store(&mut self, address: W<u16>, value: u8) {
+        let addr = address.0 as usize;
+        let value = value as usize;
+        if addr < 0x6000 {
+            return;
+        } else if addr < 0x8000 {
+            if self.prg_ram && self.mem.prg_ram.len() > 0 {
+                let mask = self.mem.prg_ram.len() - 1;
+                self.mem.prg_ram[addr & mask] = value as u8;
+            }
+            return;
+        }
+        match (addr >> 12) & 0x7 {
+            bank @ 0..=3 => self.chr_banks[bank] = value,
+            // Nametables are 1KiB pages in the last 128KiB of CHR ROM
+            page @ 4..=5 => self.nt_banks[page - 4] =
+                ((value | 0x80) * NT_SIZE) % self.mem.chr_rom.len(),
+            6 => {
+                self.mirroring = match value & 0x3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenA,
+                    _ => Mirroring::SingleScreenB,
+                };
+                self.chr_nametables = value & 0x10 != 0;
+            },
+            _ => {
+                self.prg_bank = value & 0xF;
+                self.prg_ram = value & 0x10 != 0;
+            },
+        }
+        self.update_nametables();
+    }
+
+    fn chr_peek(&self, vram: &[u8], address: W<u16>) -> u8 {
+        let addr = address.0 as usize;
+        if addr < 0x2000 {
+            self.mem.chr_rom[self.chr_address(addr)]
+        } else {
+            self.mem.chr_load(vram, address, 0)
+        }
+    }
+
+    fn prg_peek(&self, address: W<u16>) -> u8 {
+        let addr = address.0 as usize;
+        if addr < 0x6000 {
+            0
+        } else if addr < 0x8000 {
+            if self.prg_ram && self.mem.prg_ram.len() > 0 {
+                self.mem.prg_ram[addr & (self.mem.prg_ram.len() - 1)]
+            } else {
+                0
+            }
+        } else {
+            self.mem.prg_rom[self.prg_offset(address).unwrap()]
+        }
+    }
+
+    // $8000 is switchable and $C000 has the last 16KiB bank
+    fn prg_offset(&self, address: W<u16>) -> Option<usize> {
+        let addr = address.0 as usize;
+        let banks = self.mem.prg_rom.len() / 0x4000;
+        match addr {
+            0x8000..=0xBFFF => Some((self.prg_bank % banks) * 0x4000 +
+                                    (addr & 0x3FFF)),
+            0xC000..=0xFFFF => Some((banks - 1) * 0x4000 + (addr & 0x3FFF)),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, address: W<u16>) -> Option<usize> {
+        let addr = address.0 as usize;
+        if addr < 0x2000 {
+            Some(self.chr_address(addr))
+        } else {
+            self.mem.chr_offset(address, 0)
+        }
+    }
+
+    fn rom_sizes(&self) -> (usize, usize) {
+        self.mem.rom_sizes()
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn power_on(&mut self) {
+        self.chr_banks = [0; 4];
+        self.nt_banks = [0; 2];
+        self.mirroring = Mirroring::Vertical;
+        self.chr_nametables = false;
+        self.prg_bank = 0;
+        self.prg_ram = false;
+        self.update_nametables();
+    }
+
+    // Registers aren't connected to reset
+    fn soft_reset(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::*;
+    use header::Header;
+    // std
+    use std::env;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::num::Wrapping as W;
+
+    // Every 1KiB page of PRG and CHR ROM is filled with its number
+    fn game_memory(prg_size: usize, chr_size: usize, mirroring: Mirroring)
+                   -> GameMemory {
+        let pages = |size: usize| (0..size).map(|i| (i / NT_SIZE) as u8)
+                                           .collect::<Vec<u8>>()
+                                           .into_boxed_slice();
+        GameMemory {
+            prg_rom     : pages(prg_size),
+            prg_ram     : vec![0; 0x2000].into_boxed_slice(),
+            prg_bat     : vec![].into_boxed_slice(),
+            chr_rom     : pages(chr_size),
+            chr_ram     : vec![0; if chr_size == 0 {0x2000} else {0}]
+                              .into_boxed_slice(),
+            chr_bat     : vec![].into_boxed_slice(),
+            nt_ram      : vec![0; mirroring.cartridge_vram()]
+                              .into_boxed_slice(),
+            nametables  : mirroring.nametables(),
+        }
+    }
+
+    #[test]
+    pub fn test_mirroring() {
+        use mapper::Nametable::*;
+        let nametables = |mirroring: Mirroring| mirroring.nametables();
+        assert_eq!(nametables(Mirroring::Horizontal),
+                   [Vram(0), Vram(0), Vram(1), Vram(1)]);
+        assert_eq!(nametables(Mirroring::Vertical),
+                   [Vram(0), Vram(1), Vram(0), Vram(1)]);
+        assert_eq!(nametables(Mirroring::SingleScreenB),
+                   [Vram(1), Vram(1), Vram(1), Vram(1)]);
+        assert_eq!(nametables(Mirroring::FourScreen),
+                   [Vram(0), Vram(1), Cartridge(0), Cartridge(1)]);
+    }
+
+    #[test]
+    pub fn test_four_screen() {
+        let mut mapper = Nrom::new_boxed(
+            game_memory(0x4000, 0x2000, Mirroring::FourScreen));
+        let mut vram = [0u8; 0x800];
+        for (i, &address) in [0x2000, 0x2400, 0x2800, 0x2C00].iter()
+                                                              .enumerate() {
+            mapper.chr_store(&mut vram, W(address + 5), i as u8 + 1);
+        }
+        // The first two in the console VRAM, the last two on the cartridge
+        assert_eq!((vram[5], vram[0x405]), (1, 2));
+        for (i, &address) in [0x2000, 0x2400, 0x2800, 0x2C00].iter()
+                                                              .enumerate() {
+            assert_eq!(mapper.chr_load(&mut vram, W(address + 5)), i as u8 + 1);
+        }
+        // $3000-$3EFF mirrors them
+        assert_eq!(mapper.chr_load(&mut vram, W(0x3C05)), 4);
+    }
+
+    #[test]
+    pub fn test_sunsoft4() {
+        let mut mapper = Sunsoft4::new_boxed(
+            game_memory(0x20000, 0x40000, Mirroring::Vertical));
+        let mut vram = [0u8; 0x800];
+        // 2KiB CHR banks and the switchable PRG bank, $C000 has the last one
+        mapper.prg_store(W(0x9000), 5);
+        mapper.prg_store(W(0xF000), 2);
+        assert_eq!(mapper.chr_load(&mut vram, W(0x0800)), 10);
+        assert_eq!(mapper.prg_load(W(0x8000)), 32);
+        assert_eq!(mapper.prg_load(W(0xC000)), 112);
+        // CHR ROM nametables, pages from the last 128KiB, read only
+        mapper.prg_store(W(0xC000), 0x03);
+        mapper.prg_store(W(0xD000), 0x01);
+        mapper.prg_store(W(0xE000), 0x10);
+        mapper.chr_store(&mut vram, W(0x2000), 0xFF);
+        assert_eq!(mapper.chr_load(&mut vram, W(0x2000)), 0x83);
+        assert_eq!(mapper.chr_load(&mut vram, W(0x2400)), 0x81);
+        assert_eq!(mapper.chr_offset(W(0x2001)), Some(0x83 * NT_SIZE + 1));
+        // Back to VRAM with horizontal mirroring
+        mapper.prg_store(W(0xE000), 0x01);
+        mapper.chr_store(&mut vram, W(0x2400), 0x42);
+        assert_eq!(vram[0], 0x42);
+        assert_eq!(mapper.chr_offset(W(0x2000)), None);
+    }
+
+    #[test]
+    pub fn test_sunsoft4_chr_ram() {
+        let path = env::temp_dir().join("warnes_test_sunsoft4.nes");
+        let mut rom = File::create(&path).unwrap();
+        rom.write_all(b"NES\x1a\x02\x00\x40\x40\0\0\0\0\0\0\0\0").unwrap();
+        rom.write_all(&[0; 0x8000]).unwrap();
+        let mut header = Header::load_rom(&path).unwrap();
+        assert!(header.get_mapper().is_err());
+    }
 }
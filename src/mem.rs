@@ -1,9 +1,10 @@
 // nes
 use mapper::Mapper;
 use loadstore::LoadStore;
-use utils::print_mem;
-use enums::{MemState, IoState, Interrupt};
+use utils::{print_mem, Random};
+use enums::{MemState, IoState, IrqSource, RamInit};
 use ppu::PpuReadRegs;
+use cdl::{CodeDataLog, CHR_RENDERED, CHR_READ};
 // std
 use std::num::Wrapping as W;
 use std::fmt;
@@ -21,9 +22,17 @@ pub struct Memory {
     ppu_read_regs       : PpuReadRegs,
     latch               : W<u8>,
     oamdma              : Option<W<u8>>,
-    interrupt           : Option<Interrupt>,
+    apu_store           : Option<(W<u16>, W<u8>)>,
+    apu_status          : u8,
+    apu_status_read     : bool,
+    // NMI is edge triggered, the edge is kept until the CPU handles it
+    nmi_line            : bool,
+    nmi_edge            : bool,
+    // IRQ is level triggered, one bit per IrqSource holding it
+    irq_line            : u8,
     io_strobe           : u8,
     joy_key             : [u8; 2],
+    cdl                 : Option<CodeDataLog>,
 }
 
 impl Memory {
@@ -38,20 +47,83 @@ impl Memory {
             ppu_read_regs       : Default::default(),
             latch               : W(0),
             oamdma              : None,
-            interrupt           : None,
+            apu_store           : None,
+            apu_status          : 0,
+            apu_status_read     : false,
+            nmi_line            : false,
+            nmi_edge            : false,
+            irq_line            : 0,
             io_strobe           : 0,
             joy_key             : [0; 2],
+            cdl                 : None,
         }
     }
 
-    pub fn set_interrupt(&mut self, interrupt: Interrupt) {
-        self.interrupt = Some(interrupt);
+    pub fn power_on(&mut self, init: RamInit) {
+        match init {
+            RamInit::Zeros => self.ram = [0; RAM_SIZE],
+            RamInit::Ones => self.ram = [0xFF; RAM_SIZE],
+            RamInit::Random(seed) => {
+                let mut random = Random::new(seed);
+                for byte in self.ram.iter_mut() {
+                    *byte = random.next_u64() as u8;
+                }
+            },
+            RamInit::Pattern => {
+                for (i, byte) in self.ram.iter_mut().enumerate() {
+                    *byte = if i & 4 == 0 {0x00} else {0xFF};
+                }
+            },
+        }
+        self.vram = [0; VRAM_SIZE];
+        self.io_strobe = 0;
+        self.mapper.power_on();
+        self.soft_reset_latches();
+    }
+
+    // RAM and VRAM are kept
+    pub fn soft_reset(&mut self) {
+        self.mapper.soft_reset();
+        self.soft_reset_latches();
+    }
+
+    fn soft_reset_latches(&mut self) {
+        self.mem_load_status = MemState::NoState;
+        self.mem_store_status = MemState::NoState;
+        self.io_load_status = IoState::NoState;
+        self.oamdma = None;
+        self.apu_store = None;
+        self.apu_status_read = false;
+        self.nmi_line = false;
+        self.nmi_edge = false;
+        self.irq_line = 0;
+    }
+
+    pub fn set_nmi(&mut self, active: bool) {
+        if active && !self.nmi_line {
+            self.nmi_edge = true;
+        }
+        self.nmi_line = active;
+    }
+
+    // Returns true once for every NMI edge
+    pub fn get_nmi(&mut self) -> bool {
+        let edge = self.nmi_edge;
+        self.nmi_edge = false;
+        edge
+    }
+
+    pub fn set_irq(&mut self, source: IrqSource, active: bool) {
+        let bit = 1 << source as u8;
+        if active {
+            self.irq_line |= bit;
+        } else {
+            self.irq_line &= !bit;
+        }
     }
 
-    pub fn get_interrupt(&mut self) -> Option<Interrupt> {
-        let interrupt = self.interrupt;
-        self.interrupt = None;
-        interrupt
+    pub fn irq(&self) -> bool {
+        self.irq_line != 0 || self.mapper.irq()
     }
 
     pub fn get_latch(&mut self) -> (W<u8>, MemState) {
@@ -60,12 +132,41 @@ impl Memory {
         status
     }
 
+    // The register write of this cycle, before the PPU takes it
+    pub fn store_state(&self) -> (W<u8>, MemState) {
+        (self.latch, self.mem_store_status)
+    }
+
+    pub fn pending_oamdma(&self) -> Option<W<u8>> {
+        self.oamdma
+    }
+
+    pub fn mapper_irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
     pub fn get_oamdma(&mut self) -> Option<W<u8>> {
         let status = self.oamdma;
         self.oamdma = None;
         status
     }
 
+    pub fn get_apu_store(&mut self) -> Option<(W<u16>, W<u8>)> {
+        let status = self.apu_store;
+        self.apu_store = None;
+        status
+    }
+
+    pub fn apu_status_read(&mut self) -> bool {
+        let status = self.apu_status_read;
+        self.apu_status_read = false;
+        status
+    }
+
+    pub fn set_apu_status(&mut self, status: u8) {
+        self.apu_status = status;
+    }
+
     pub fn ppu_load_status(&mut self) -> MemState {
         let status = self.mem_load_status;
         self.mem_load_status = MemState::NoState;
@@ -76,10 +177,78 @@ impl Memory {
         self.ppu_read_regs = regs;
     }
 
+    // Rendering fetches
     pub fn chr_load(&mut self, address: W<u16>) -> W<u8> {
+        self.log_chr(address, CHR_RENDERED);
+        W(self.mapper.chr_load(&mut self.vram[..], address))
+    }
+
+    // Reads through PPUDATA
+    pub fn chr_read(&mut self, address: W<u16>) -> W<u8> {
+        self.log_chr(address, CHR_READ);
         W(self.mapper.chr_load(&mut self.vram[..], address))
     }
 
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLog>) {
+        self.cdl = cdl;
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_ref()
+    }
+
+    pub fn rom_sizes(&self) -> (usize, usize) {
+        self.mapper.rom_sizes()
+    }
+
+    // Marks the ROM byte mapped at a CPU address
+    pub fn log_prg(&mut self, address: W<u16>, flags: u8) {
+        if let Some(ref mut cdl) = self.cdl {
+            if let Some(offset) = self.mapper.prg_offset(address) {
+                cdl.mark_prg(offset, address, flags);
+            }
+        }
+    }
+
+    fn log_chr(&mut self, address: W<u16>, flags: u8) {
+        if let Some(ref mut cdl) = self.cdl {
+            if let Some(offset) = self.mapper.chr_offset(address) {
+                cdl.mark_chr(offset, flags);
+            }
+        }
+    }
+
+    // Reads a CPU address without touching any latch, registers return
+    // the value a load would return right now
+    pub fn peek(&self, address: W<u16>) -> W<u8> {
+        let addr = address.0;
+        let value = if addr < 0x2000 {
+            self.ram[(addr & 0x7FF) as usize]
+        } else if addr < 0x4000 {
+            match addr & 0x7 {
+                2 => self.ppu_read_regs.status,
+                4 => self.ppu_read_regs.oam,
+                7 => self.ppu_read_regs.data,
+                _ => 0,
+            }
+        } else if addr < 0x4020 {
+            match addr {
+                0x4015 => self.apu_status,
+                0x4016 => self.joy_key[0],
+                0x4017 => self.joy_key[1],
+                _      => 0,
+            }
+        } else {
+            self.mapper.prg_peek(address)
+        };
+        W(value)
+    }
+
+    // Reads a PPU address below the palettes without side effects
+    pub fn chr_peek(&self, address: W<u16>) -> W<u8> {
+        W(self.mapper.chr_peek(&self.vram[..], address))
+    }
+
     pub fn chr_store(&mut self, address: W<u16>, value: W<u8>){
         self.mapper.chr_store(&mut self.vram[..], address, value.0);
     }
@@ -124,6 +293,11 @@ impl LoadStore for Memory {
             match addr {
                 // OAMDMA is Write only, TODO: Check what happens
                 0x4014 => 0,
+                0x4015 => {
+                    self.apu_status_read = true;
+                    self.mem_load_status = MemState::Io;
+                    self.apu_status
+                },
                 0x4016 => {
                     self.io_load_status = IoState::GamePad1;
                     self.mem_load_status = MemState::Io;
@@ -173,6 +347,9 @@ impl LoadStore for Memory {
                 0x4016 => {
                     self.io_strobe = val;
                 },
+                0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                    self.apu_store = Some((address, value));
+                },
                 _      => (),
             }
         } else {
@@ -1,62 +1,229 @@
-extern crate sdl2;
-extern crate time;
-
 // STD
 use std::path::Path;
+use std::num::Wrapping as W;
 
 // Custom stuff
 use header::Header;
 use cpu::Cpu;
-use ppu::Ppu;
+use ppu::{Ppu, DebugLayers};
+use apu::Apu;
 use mem::Memory as Mem;
 use controller::Controller as Pad;
+use trace::Trace;
+use cdl::CodeDataLog;
+use events::{EventLog, Event, EventKind};
+use enums::{MemState, RamInit, Region};
 
 pub struct Nes {
     cpu : Cpu,
     ppu : Ppu,
+    apu : Apu,
     mem : Mem,
     pad : Pad,
     keys : [[u8; 8]; 2],
+    trace : Option<Trace>,
+    events : Option<EventLog>,
+    // Mapper IRQ line when the events were last logged
+    mapper_irq : bool,
+    region : Region,
+    // PPU dots owed to the CPU, in fractions of the region ratio
+    ppu_debt : u32,
 }
 
 impl Nes {
     pub fn new<P: AsRef<Path>> (rom_path: P) -> Result<Nes, String> {
-        let mapper = Header::load_rom(rom_path)?.get_mapper()?;
-        Ok (
-            Nes {
-                cpu : Default::default(),
-                ppu : Ppu::new(),
-                mem : Mem::new(mapper),
-                pad : Pad::new(),
-                keys : [[0u8; 8]; 2],
-            }
-        )
+        let mut header = Header::load_rom(rom_path)?;
+        let mapper = header.get_mapper()?;
+        let mut nes = Nes {
+            cpu : Default::default(),
+            ppu : Ppu::new(),
+            apu : Apu::new(),
+            mem : Mem::new(mapper),
+            pad : Pad::new(),
+            keys : [[0u8; 8]; 2],
+            trace : None,
+            events : None,
+            mapper_irq : false,
+            region : Region::Ntsc,
+            ppu_debt : 0,
+        };
+        nes.set_region(header.region().unwrap_or(Region::Ntsc));
+        Ok(nes)
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_debt = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.ppu.set_sprite_limit(limit);
+    }
+
+    pub fn set_debug_layers(&mut self, layers: DebugLayers) {
+        self.ppu.set_debug_layers(layers);
     }
 
     // This function does a complete CPU cycle
-    // Including joy I/O and 3 PPU cycles.
+    // Including joy I/O, APU and 3 PPU cycles (3.2 on PAL).
     pub fn cycle(&mut self) {
         self.pad.cycle(&mut self.mem, &self.keys);
         self.cpu.cycle(&mut self.mem);
-        self.ppu.cycle(&mut self.mem);
-        self.ppu.cycle(&mut self.mem);
-        self.ppu.cycle(&mut self.mem);
+        if self.cpu.fetched_opcode() {
+            self.trace_instruction();
+        }
+        if self.events.is_some() {
+            self.log_events();
+        }
+        self.apu.cycle(&mut self.mem);
+        let (dots, cycles) = self.region.ppu_ratio();
+        self.ppu_debt += dots;
+        while self.ppu_debt >= cycles {
+            self.ppu.cycle(&mut self.mem);
+            self.ppu_debt -= cycles;
+        }
+        if let Some(ref mut events) = self.events {
+            events.end_frame(self.ppu.frame_data().0);
+        }
     }
 
     // This function steps a single cpu instruction
     pub fn step(&mut self) {
         let next = self.cpu.instruction_count() + 1;
-        while self.cpu.instruction_count() != next {
+        while self.cpu.instruction_count() != next && !self.cpu.jammed() {
             self.cycle();
         }
     }
 
+    fn trace_instruction(&mut self) {
+        let result = match self.trace {
+            Some(ref mut trace) => trace.log(&self.cpu, &self.ppu, &self.mem),
+            None => return,
+        };
+        if let Err(error) = result {
+            println!("Trace stopped: {}", error);
+            self.trace = None;
+        }
+    }
+
+    // Logged at the dot the PPU sees the write, IRQs when the CPU sees them
+    fn log_events(&mut self) {
+        let (value, state) = self.mem.store_state();
+        let write = match state {
+            // OAM DMA writes to $2004 are covered by the $4014 one
+            _ if self.cpu.in_dma() => None,
+            MemState::PpuCtrl   => Some((0x2000, value)),
+            MemState::PpuMask   => Some((0x2001, value)),
+            MemState::OamAddr   => Some((0x2003, value)),
+            MemState::OamData   => Some((0x2004, value)),
+            MemState::PpuScroll => Some((0x2005, value)),
+            MemState::PpuAddr   => Some((0x2006, value)),
+            MemState::PpuData   => Some((0x2007, value)),
+            MemState::Io => self.mem.pending_oamdma().map(|page| (0x4014, page)),
+            _ => None,
+        };
+        let irq = self.mem.mapper_irq();
+        let irq_edge = irq && !self.mapper_irq;
+        self.mapper_irq = irq;
+        let (scanline, dot) = self.ppu.position();
+        let events = match self.events {
+            Some(ref mut events) => events,
+            None => return,
+        };
+        if let Some((address, value)) = write {
+            events.record(Event {
+                scanline    : scanline,
+                dot         : dot,
+                kind        : EventKind::Register(address),
+                value       : value.0,
+            });
+        }
+        if irq_edge {
+            events.record(Event {
+                scanline    : scanline,
+                dot         : dot,
+                kind        : EventKind::MapperIrq,
+                value       : 0,
+            });
+        }
+    }
+
+    pub fn start_events(&mut self) {
+        self.events = Some(EventLog::new());
+        self.mapper_irq = self.mem.mapper_irq();
+    }
+
+    pub fn stop_events(&mut self) {
+        self.events = None;
+    }
+
+    pub fn events(&self) -> Option<&EventLog> {
+        self.events.as_ref()
+    }
+
+    pub fn save_events<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        match self.events {
+            Some(ref events) => events.save(path),
+            None => err!("The event logger isn't running"),
+        }
+    }
+
+    // Starts the code/data logger, resuming a saved log when given
+    pub fn start_cdl(&mut self, resume: Option<&Path>) -> Result<(), String> {
+        let (prg_size, chr_size) = self.mem.rom_sizes();
+        let cdl = match resume {
+            Some(path) => CodeDataLog::load(path, prg_size, chr_size)?,
+            None => CodeDataLog::new(prg_size, chr_size),
+        };
+        self.mem.set_cdl(Some(cdl));
+        Ok(())
+    }
+
+    pub fn stop_cdl(&mut self) {
+        self.mem.set_cdl(None);
+    }
+
+    pub fn save_cdl<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        match self.mem.cdl() {
+            Some(cdl) => cdl.save(path),
+            None => err!("The code/data logger isn't running"),
+        }
+    }
+
+    // Replaces the trace logger, the previous one is flushed
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        if let Some(mut previous) = self.trace.take() {
+            if let Err(error) = previous.flush() {
+                println!("Couldn't flush trace: {}", error);
+            }
+        }
+        self.trace = trace;
+    }
+
     pub fn set_keys(&mut self, keys: &[[u8; 8]; 2]){
         self.keys = *keys;
     }
 
-    pub fn reset(&mut self) {
-        self.cpu.reset(&mut self.mem);
+    pub fn power_on(&mut self, ram: RamInit) {
+        self.mem.power_on(ram);
+        self.cpu.power_on();
+        self.ppu.power_on();
+        self.apu.power_on();
+        self.pad = Pad::new();
+    }
+
+    // Like the console reset button, memory is kept
+    pub fn soft_reset(&mut self) {
+        self.mem.soft_reset();
+        self.cpu.soft_reset();
+        self.ppu.soft_reset();
+        self.apu.soft_reset();
     }
 
     pub fn cpu(&self) -> &Cpu {
@@ -66,14 +233,40 @@ impl Nes {
     pub fn ppu(&self) -> &Ppu {
         &self.ppu
     }
+
+    // Samples made since they were last cleared
+    pub fn audio_samples(&mut self) -> &mut Vec<i16> {
+        self.apu.samples()
+    }
+
+    pub fn memory(&self) -> &Mem {
+        &self.mem
+    }
+
+    // Side effect free reads of the CPU and PPU address spaces
+    pub fn peek(&self, address: W<u16>) -> W<u8> {
+        self.mem.peek(address)
+    }
+
+    pub fn ppu_peek(&self, address: W<u16>) -> W<u8> {
+        self.ppu.peek(&self.mem, address)
+    }
 }
 
 #[cfg(test)]
 mod test {
     // nes
     use nes::Nes;
+    use enums::{RamInit, Region};
+    use trace::Trace;
+    use events::EventKind;
+    use enums::IrqSource;
+    use loadstore::LoadStore;
+    use ppu::DebugLayers;
 
     // std
+    use std::env;
+    use std::num::Wrapping as W;
     use std::io::BufReader;
     use std::io::prelude::*;
     use std::fs::File;
@@ -82,12 +275,59 @@ mod test {
         ($a:expr, $b:expr, $format:expr) => (assert!($a == $b, $format, $a, $b))
     }
 
+    const NMI_HANDLER : u16 = 0xD000;
+    const IRQ_HANDLER : u16 = 0xE000;
+
+    // NROM with the program at $C000 and NOPs everywhere else, after the
+    // reset sequence
+    fn program_nes(name: &str, program: &[u8]) -> Nes {
+        let path = env::temp_dir().join(format!("warnes_test_{}.nes", name));
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0xD0, 0x00, 0xC0, 0x00, 0xE0]);
+        let mut rom = File::create(&path).unwrap();
+        rom.write_all(b"NES\x1a\x01\x00\0\0\0\0\0\0\0\0\0\0").unwrap();
+        rom.write_all(&prg).unwrap();
+        let mut nes = Nes::new(&path).unwrap();
+        nes.power_on(RamInit::Zeros);
+        nes
+    }
+
+    // Runs the CPU alone until the opcode at address is fetched, returns
+    // the cycle count after the fetch
+    fn run_to(nes: &mut Nes, address: u16) -> u64 {
+        for _ in 0..100 {
+            nes.cpu.cycle(&mut nes.mem);
+            if nes.cpu.fetched_opcode() &&
+               nes.cpu.execution().pc == W(address) {
+                return nes.cpu.cycle_count();
+            }
+        }
+        panic!("${:04X} not reached", address);
+    }
+
+    fn run(nes: &mut Nes, cycles: u32) {
+        for _ in 0..cycles {
+            nes.cpu.cycle(&mut nes.mem);
+        }
+    }
+
+    // Return address and flags pushed by the last interrupt
+    fn pushed(nes: &Nes) -> (u16, u8) {
+        let sp = nes.cpu().registers().SP.0 as u16;
+        let byte = |offset: u16| nes.peek(W(0x100 + sp + offset)).0;
+        ((byte(3) as u16) << 8 | byte(2) as u16, byte(1))
+    }
+
     #[test]
     pub fn test_cpu() {
         let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
         let file = File::open("test/nestest_direct.out").unwrap();
         let log = BufReader::new(file);
-        nes.reset();
+        nes.power_on(RamInit::Zeros);
+        // The log was taken with an instant reset, skip the reset sequence
+        nes.step();
+        let reset_cycles = nes.cpu().cycle_count();
         for buffer in log.lines() {
             let regs = nes.cpu().registers();
             let buffer = buffer.unwrap();
@@ -102,9 +342,559 @@ mod test {
             assert_equal!(regs.Y.0, r[2], "Y {:02X} != {:02X}");
             assert_equal!(regs.P.0 | 0x20, r[3], "P {:02X} != {:02X}");
             assert_equal!(regs.SP.0, r[4], "SP {:02X} != {:02X}");
-            assert_equal!((nes.cpu().cycle_count() * 3) % 341,
+            assert_equal!(((nes.cpu().cycle_count() - reset_cycles) * 3) % 341,
                           data[6].parse::<u64>().unwrap(), "Cycles {} != {}");
             nes.step();
         }
     }
+
+    #[test]
+    pub fn test_ram_init() {
+        let ram = |init: RamInit| {
+            let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+            nes.power_on(init);
+            (0..0x800).map(|address| nes.peek(W(address)).0)
+                      .collect::<Vec<u8>>()
+        };
+        assert!(ram(RamInit::Zeros).iter().all(|&byte| byte == 0x00));
+        assert!(ram(RamInit::Ones).iter().all(|&byte| byte == 0xFF));
+        // Four bytes of $00 then four of $FF
+        assert!(ram(RamInit::Pattern).iter().enumerate().all(|(i, &byte)| {
+            byte == if i & 4 == 0 {0x00} else {0xFF}
+        }));
+        // SplitMix64 starts at $E220A8397B1DCDAF from seed 0, each byte is
+        // the low one of an output
+        let random = ram(RamInit::Random(0));
+        assert_eq!(random[0], 0xAF);
+        assert!(random == ram(RamInit::Random(0)));
+        assert!(random != ram(RamInit::Random(1)));
+    }
+
+    #[test]
+    pub fn test_reset_memory() {
+        let mut nes = program_nes("reset_memory", &[]);
+        nes.mem.store(W(0x0123), W(0x45));
+        nes.mem.chr_store(W(0x2001), W(0x67));
+        // A reset keeps RAM and VRAM, powering on starts them again
+        nes.soft_reset();
+        assert_eq!((nes.peek(W(0x0123)), nes.mem.chr_peek(W(0x2001))),
+                   (W(0x45), W(0x67)));
+        nes.power_on(RamInit::Ones);
+        assert_eq!((nes.peek(W(0x0123)), nes.mem.chr_peek(W(0x2001))),
+                   (W(0xFF), W(0x00)));
+    }
+
+    #[test]
+    pub fn test_nmi_polling() {
+        // NOPs, the NMI is seen before the last cycle of the first one
+        let mut nes = program_nes("nmi_polling", &[]);
+        let start = run_to(&mut nes, 0xC000);
+        nes.mem.set_nmi(true);
+        // Its last cycle and the 7 of the interrupt before the fetch
+        assert_eq!(run_to(&mut nes, NMI_HANDLER), start + 9);
+        assert_eq!(pushed(&nes), (0xC001, 0x24));
+        // Too late for the first one
+        let mut nes = program_nes("nmi_polling_late", &[]);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 1);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes).0, 0xC002);
+    }
+
+    #[test]
+    pub fn test_branch_polling() {
+        // BNE taken to the next instruction, no page crossed
+        let program = [0xD0, 0x00];
+        let mut nes = program_nes("branch_polling", &program);
+        run_to(&mut nes, 0xC000);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes).0, 0xC002);
+        // Only the operand fetch polls, the NOP after it runs first
+        let mut nes = program_nes("branch_no_polling", &program);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 1);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes).0, 0xC003);
+        // LDA $00 takes as long and polls on its last cycle
+        let mut nes = program_nes("lda_polling", &[0xA5, 0x00]);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 1);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes).0, 0xC002);
+    }
+
+    #[test]
+    pub fn test_irq_latency() {
+        // CLI and PLP change I on their last cycle, after the poll, so the
+        // IRQ waits for the NOP. SEI gets interrupted with I pushed set.
+        let programs : [(&str, &[u8], (u16, u8)); 3] = [
+            ("cli", &[0x58], (0xC002, 0x20)),
+            ("sei", &[0x58, 0x78], (0xC002, 0x24)),
+            ("plp", &[0x28], (0xC002, 0x20)),
+        ];
+        for &(name, program, expected) in programs.iter() {
+            let mut nes = program_nes(name, program);
+            nes.mem.set_irq(IrqSource::FrameCounter, true);
+            run_to(&mut nes, IRQ_HANDLER);
+            assert_eq!(pushed(&nes), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    pub fn test_nmi_hijack() {
+        // Seen before BRK pushes the flags, it goes to the NMI handler
+        let mut nes = program_nes("brk_hijack", &[0x00]);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 3);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes), (0xC002, 0x34));
+        // One cycle later BRK finishes and the NMI follows its first
+        // handler instruction
+        let mut nes = program_nes("brk_no_hijack", &[0x00]);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 4);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, IRQ_HANDLER);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes), (IRQ_HANDLER + 1, 0x24));
+        // The same with an IRQ after CLI and a NOP
+        let mut nes = program_nes("irq_hijack", &[0x58]);
+        nes.mem.set_irq(IrqSource::FrameCounter, true);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 6);
+        nes.mem.set_nmi(true);
+        run_to(&mut nes, NMI_HANDLER);
+        assert_eq!(pushed(&nes), (0xC002, 0x20));
+    }
+
+    #[test]
+    pub fn test_unofficial_operations() {
+        // Program, A, X and P after it
+        let programs : [(&str, &[u8], u8, u8, u8); 9] = [
+            // LDA #$FF, ANC #$80
+            ("anc", &[0xA9, 0xFF, 0x0B, 0x80], 0x80, 0x00, 0x85),
+            // LDA #$FF, ALR #$03
+            ("alr", &[0xA9, 0xFF, 0x4B, 0x03], 0x01, 0x00, 0x05),
+            // LDA #$FF, CLC, ARR #$40: C is bit 6, V is bit 6 xor bit 5
+            ("arr", &[0xA9, 0xFF, 0x18, 0x6B, 0x40], 0x20, 0x00, 0x44),
+            // LDA #$FF, SEC, ARR #$80
+            ("arr_carry", &[0xA9, 0xFF, 0x38, 0x6B, 0x80], 0xC0, 0x00, 0xC5),
+            // LDA #$FF, CLC, ARR #$01
+            ("arr_zero", &[0xA9, 0xFF, 0x18, 0x6B, 0x01], 0x00, 0x00, 0x06),
+            // LDA #$FF, LDX #$05, AXS #$06
+            ("axs", &[0xA9, 0xFF, 0xA2, 0x05, 0xCB, 0x06], 0xFF, 0xFF, 0x84),
+            // LDA #$0F, LDX #$FC, AXS #$02
+            ("axs_carry", &[0xA9, 0x0F, 0xA2, 0xFC, 0xCB, 0x02], 0x0F, 0x0A,
+             0x05),
+            // LDA #$11, LDX #$FF, XAA #$F7
+            ("xaa", &[0xA9, 0x11, 0xA2, 0xFF, 0x8B, 0xF7], 0xF7, 0xFF, 0x84),
+            // LDA #$F3, STA $0100, LDY #$01, LAS $00FF,Y: ANDed with SP $FD
+            ("las", &[0xA9, 0xF3, 0x8D, 0x00, 0x01, 0xA0, 0x01, 0xBB, 0xFF,
+                      0x00], 0xF1, 0xF1, 0x84),
+        ];
+        for &(name, program, a, x, p) in programs.iter() {
+            let mut nes = program_nes(name, program);
+            run_to(&mut nes, 0xC000 + program.len() as u16);
+            let regs = nes.cpu().registers();
+            assert_eq!((regs.A.0, regs.X.0, regs.P.0), (a, x, p), "{}", name);
+        }
+    }
+
+    #[test]
+    pub fn test_unofficial_stores() {
+        // Program, where it stores and the value, ANDed with the base
+        // address high byte plus one, which is $06
+        let programs : [(&str, &[u8], u16, u8); 6] = [
+            // LDX #$07, LDY #$01, SHX $0500,Y
+            ("shx", &[0xA2, 0x07, 0xA0, 0x01, 0x9E, 0x00, 0x05], 0x0501, 0x06),
+            // LDX #$03, LDY #$01, SHX $05FF,Y: the page cross replaces the
+            // high byte with the value
+            ("shx_page", &[0xA2, 0x03, 0xA0, 0x01, 0x9E, 0xFF, 0x05], 0x0200,
+             0x02),
+            // LDY #$03, LDX #$01, SHY $05FF,X
+            ("shy_page", &[0xA0, 0x03, 0xA2, 0x01, 0x9C, 0xFF, 0x05], 0x0200,
+             0x02),
+            // LDA #$FE, LDX #$07, LDY #$01, AHX $0500,Y
+            ("ahx", &[0xA9, 0xFE, 0xA2, 0x07, 0xA0, 0x01, 0x9F, 0x00, 0x05],
+             0x0501, 0x06),
+            // LDA #$05, STA $11, LDA #$FF, LDX #$07, LDY #$01, AHX ($10),Y
+            ("ahx_indirect", &[0xA9, 0x05, 0x85, 0x11, 0xA9, 0xFF, 0xA2, 0x07,
+                               0xA0, 0x01, 0x93, 0x10], 0x0501, 0x06),
+            // LDA #$F7, LDX #$3F, LDY #$01, TAS $0500,Y: SP = A & X
+            ("tas", &[0xA9, 0xF7, 0xA2, 0x3F, 0xA0, 0x01, 0x9B, 0x00, 0x05],
+             0x0501, 0x06),
+        ];
+        for &(name, program, address, value) in programs.iter() {
+            let mut nes = program_nes(name, program);
+            run_to(&mut nes, 0xC000 + program.len() as u16);
+            assert_eq!(nes.peek(W(address)), W(value), "{}", name);
+            // Nothing at the address without the page cross
+            assert_eq!(nes.peek(W(0x0600)), W(0), "{}", name);
+        }
+        let mut nes = program_nes("tas_sp", &[0xA9, 0xF7, 0xA2, 0x3F, 0x9B,
+                                              0x00, 0x05]);
+        run_to(&mut nes, 0xC007);
+        assert_eq!(nes.cpu().registers().SP, W(0x37));
+    }
+
+    #[test]
+    pub fn test_jam() {
+        let mut nes = program_nes("jam", &[0x02]);
+        run_to(&mut nes, 0xC000);
+        run(&mut nes, 10);
+        assert!(nes.cpu().jammed());
+        assert_eq!(nes.cpu().registers().PC, W(0xC001));
+        // Only a reset gets it running again
+        nes.soft_reset();
+        run_to(&mut nes, 0xC000);
+        assert!(!nes.cpu().jammed());
+    }
+
+    #[test]
+    pub fn test_trace() {
+        let path = env::temp_dir().join("warnes_test_trace.log");
+        let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+        nes.power_on(RamInit::Zeros);
+        nes.set_trace(Some(Trace::to_file(&path, vec![]).unwrap()));
+        // The reset sequence and three instructions
+        for _ in 0..4 {
+            nes.step();
+        }
+        nes.set_trace(None);
+        let mut log = String::new();
+        File::open(&path).unwrap().read_to_string(&mut log).unwrap();
+        // First lines of nestest.log
+        let expected = [
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+            "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+        ];
+        assert_eq!(log.lines().collect::<Vec<&str>>(), expected);
+    }
+
+    // CPU cycles from the start of frame 1 to the start of frame count + 1
+    fn frame_cycles(region: Region, count: u64, mask: u8) -> u64 {
+        let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+        nes.set_region(region);
+        nes.power_on(RamInit::Zeros);
+        while nes.ppu().frame_data().0 < 1 {
+            nes.cycle();
+        }
+        nes.mem.store(W(0x2001), W(mask));
+        let start = nes.cpu().cycle_count();
+        while nes.ppu().frame_data().0 < count + 1 {
+            nes.cycle();
+        }
+        nes.cpu().cycle_count() - start
+    }
+
+    #[test]
+    pub fn test_region_timing() {
+        // 312 lines of 341 dots, 3.2 dots per cycle on PAL and 3 on Dendy
+        assert_eq!(frame_cycles(Region::Pal, 2, 0), 312 * 341 * 2 * 5 / 16);
+        assert_eq!(frame_cycles(Region::Dendy, 2, 0), 312 * 341 * 2 / 3);
+    }
+
+    #[test]
+    pub fn test_odd_frame_skip() {
+        // Frames 1, 3 and 5 are a dot shorter only with rendering on
+        assert_eq!(frame_cycles(Region::Ntsc, 6, 0), 262 * 341 * 6 / 3);
+        assert_eq!(frame_cycles(Region::Ntsc, 6, 0x08), (262 * 341 * 6 - 3) / 3);
+        assert_eq!(frame_cycles(Region::Pal, 2, 0x08), 312 * 341 * 2 * 5 / 16);
+    }
+
+    #[test]
+    pub fn test_frame_phase() {
+        // A frame moves the subcarrier 4 clocks, the dot skipped on odd
+        // frames with rendering on makes it 8
+        let phases = |mask: u8| {
+            let mut nes = ppu_nes("frame_phase");
+            ppu_write(&mut nes, 0x2001, mask);
+            (2..8).map(|frame| {
+                while nes.ppu().frame_data().0 < frame {
+                    nes.ppu.cycle(&mut nes.mem);
+                }
+                nes.ppu().frame_phase()
+            }).collect::<Vec<_>>()
+        };
+        let off = phases(0);
+        for pair in off.windows(2) {
+            assert_eq!(pair[1], (pair[0] + 4) % 12);
+        }
+        let on = phases(0x08);
+        assert_ne!(on[0], on[1]);
+        for pair in on.windows(3) {
+            assert_eq!(pair[0], pair[2]);
+        }
+    }
+
+    #[test]
+    pub fn test_oam_registers() {
+        let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+        nes.power_on(RamInit::Zeros);
+        let write = |nes: &mut Nes, address: u16, value: u8| {
+            nes.mem.store(W(address), W(value));
+            nes.cycle();
+        };
+        // Attribute bits 2-4 read back as 0
+        write(&mut nes, 0x2003, 0x02);
+        write(&mut nes, 0x2004, 0xFF);
+        write(&mut nes, 0x2004, 0xFF);
+        write(&mut nes, 0x2003, 0x02);
+        assert_eq!(nes.peek(W(0x2004)), W(0xE3));
+        write(&mut nes, 0x2003, 0x03);
+        assert_eq!(nes.peek(W(0x2004)), W(0xFF));
+    }
+
+    // Runs the PPU alone on the NOP program, past the first vblank so the
+    // registers can be written
+    fn ppu_nes(name: &str) -> Nes {
+        let mut nes = program_nes(name, &[]);
+        while nes.ppu().frame_data().0 < 1 {
+            nes.ppu.cycle(&mut nes.mem);
+        }
+        nes
+    }
+
+    // The PPU takes the write on its next dot
+    fn ppu_write(nes: &mut Nes, address: u16, value: u8) {
+        nes.mem.store(W(address), W(value));
+        nes.ppu.cycle(&mut nes.mem);
+    }
+
+    fn ppu_run_to(nes: &mut Nes, scanline: usize, dot: usize) {
+        while nes.ppu().position() != (scanline, dot) {
+            nes.ppu.cycle(&mut nes.mem);
+        }
+    }
+
+    fn fill_oam(nes: &mut Nes, sprite: &dyn Fn(usize) -> [u8; 4]) {
+        ppu_write(nes, 0x2003, 0);
+        for n in 0..64 {
+            for &value in sprite(n).iter() {
+                ppu_write(nes, 0x2004, value);
+            }
+        }
+    }
+
+    // Writes data from a PPU address through $2006 and $2007
+    fn ppu_fill(nes: &mut Nes, address: u16, data: &[u8]) {
+        ppu_write(nes, 0x2006, (address >> 8) as u8);
+        ppu_write(nes, 0x2006, address as u8);
+        // The address gets to the PPU a few dots later
+        for _ in 0..2 {
+            nes.ppu.cycle(&mut nes.mem);
+        }
+        for &value in data {
+            ppu_write(nes, 0x2007, value);
+        }
+    }
+
+    // Twelve sprites drawn on lines $11-$18 every 16 pixels, over the
+    // background row on lines $10-$17, both solid with tile 1. Rendering
+    // starts at the next vblank with the mask given.
+    fn scene_nes(name: &str, mask: u8) -> Nes {
+        let mut nes = ppu_nes(name);
+        ppu_fill(&mut nes, 0x0010, &[0xFF; 8]);
+        ppu_fill(&mut nes, 0x2040, &[0x01; 32]);
+        ppu_fill(&mut nes, 0x3F00, &[0x0F, 0x21]);
+        ppu_fill(&mut nes, 0x3F11, &[0x16]);
+        // Rendering starts from the top left
+        ppu_fill(&mut nes, 0x0000, &[]);
+        fill_oam(&mut nes, &|n| match n {
+            0..=11 => [0x10, 0x01, 0x00, n as u8 * 16],
+            _ => [0xFF; 4],
+        });
+        ppu_run_to(&mut nes, 241, 0);
+        ppu_write(&mut nes, 0x2001, mask);
+        nes
+    }
+
+    // The scene up to its last sprite line, with what the CPU can read
+    // from $2002 and $2004 on every dot
+    fn sprite_scene(name: &str, limit: bool, layers: DebugLayers)
+                    -> (Nes, Vec<(u8, u8)>) {
+        let mut nes = scene_nes(name, 0x1E);
+        nes.set_sprite_limit(limit);
+        nes.set_debug_layers(layers);
+        let mut reads = Vec::new();
+        while nes.ppu().position() != (0x19, 0) {
+            reads.push((nes.peek(W(0x2002)).0, nes.peek(W(0x2004)).0));
+            nes.ppu.cycle(&mut nes.mem);
+        }
+        (nes, reads)
+    }
+
+    // Palette color at x on the first line of the sprites
+    fn sprite_line(nes: &Nes, x: usize) -> u16 {
+        nes.ppu().frame_data().1[0x11][x] & 0x3F
+    }
+
+    #[test]
+    pub fn test_no_sprite_limit() {
+        let (limited, limited_reads) =
+            sprite_scene("sprite_limit", true, DebugLayers::default());
+        let (unlimited, reads) =
+            sprite_scene("no_sprite_limit", false, DebugLayers::default());
+        for n in 0..12 {
+            let x = n * 16 + 4;
+            assert_eq!(sprite_line(&unlimited, x), 0x16, "{}", n);
+            assert_eq!(sprite_line(&limited, x), if n < 8 {0x16} else {0x21},
+                       "{}", n);
+        }
+        // Overflow and sprite 0 hit are set on the same dots, and OAM
+        // evaluation reads the same bytes
+        assert_eq!(reads.last().unwrap().0 & 0x60, 0x60);
+        assert!(reads == limited_reads);
+    }
+
+    #[test]
+    pub fn test_debug_layers() {
+        let (_, reads) = sprite_scene("layers", true, DebugLayers::default());
+        let hidden = |name: &str, layers: DebugLayers| {
+            let (nes, layer_reads) = sprite_scene(name, true, layers);
+            // Sprite 0 hit, overflow and the buses don't see the layers
+            assert!(layer_reads == reads, "{:?}", layers);
+            nes
+        };
+        let nes = hidden("layers_background", DebugLayers {
+            hide_background : true,
+            ..Default::default()
+        });
+        assert_eq!((sprite_line(&nes, 4), sprite_line(&nes, 12)), (0x16, 0x0F));
+        let nes = hidden("layers_sprites", DebugLayers {
+            hide_sprites    : true,
+            ..Default::default()
+        });
+        assert_eq!((sprite_line(&nes, 4), sprite_line(&nes, 12)), (0x21, 0x21));
+        // Sprite 0 hidden still hits
+        let nes = hidden("layers_entries", DebugLayers {
+            hidden_sprites  : 0x3,
+            ..Default::default()
+        });
+        assert_eq!((sprite_line(&nes, 4), sprite_line(&nes, 20),
+                    sprite_line(&nes, 36)), (0x21, 0x21, 0x16));
+    }
+
+    #[test]
+    pub fn test_ppu_mask() {
+        // Pixels 4 and 12 of the first sprite line, a sprite and background
+        let pixels = |name: &str, mask: u8| {
+            let mut nes = scene_nes(name, mask);
+            ppu_run_to(&mut nes, 0x12, 0);
+            let line = &nes.ppu().frame_data().1[0x11];
+            (line[4], line[12])
+        };
+        assert_eq!(pixels("mask", 0x1E), (0x16, 0x21));
+        // Grayscale leaves the brightness column of the colors
+        assert_eq!(pixels("mask_grayscale", 0x1F), (0x10, 0x20));
+        // The left 8 pixels of each layer can be clipped
+        assert_eq!(pixels("mask_left", 0x18), (0x0F, 0x21));
+        assert_eq!(pixels("mask_left_background", 0x1A), (0x21, 0x21));
+        assert_eq!(pixels("mask_left_sprites", 0x1C), (0x16, 0x21));
+        // Red and blue emphasis above the color
+        assert_eq!(pixels("mask_emphasis", 0xBE), (0x16 | 0x5 << 6,
+                                                    0x21 | 0x5 << 6));
+    }
+
+    #[test]
+    pub fn test_oam_rendering() {
+        let mut nes = ppu_nes("oam_rendering");
+        fill_oam(&mut nes, &|n| [n as u8 * 4, 0, 0, 0]);
+        ppu_write(&mut nes, 0x2001, 0x18);
+        // Secondary OAM is being cleared, $2004 reads the $FF written to it
+        ppu_run_to(&mut nes, 10, 20);
+        assert_eq!(nes.peek(W(0x2004)), W(0xFF));
+        // Writes are dropped and only move OAMADDR to the next sprite
+        ppu_write(&mut nes, 0x2003, 0x10);
+        ppu_write(&mut nes, 0x2004, 0xAB);
+        assert_eq!(nes.ppu().oam()[0x10], 0x10);
+        ppu_write(&mut nes, 0x2001, 0x00);
+        assert_eq!(nes.peek(W(0x2004)), W(0x14));
+    }
+
+    #[test]
+    pub fn test_oam_corruption() {
+        let mut nes = ppu_nes("oam_corruption");
+        fill_oam(&mut nes, &|n| [n as u8, n as u8, n as u8, n as u8]);
+        // Rendering starts at prerender with OAMADDR at $24
+        ppu_run_to(&mut nes, 241, 0);
+        ppu_write(&mut nes, 0x2003, 0x24);
+        ppu_write(&mut nes, 0x2001, 0x18);
+        let row = nes.ppu().oam()[0x20..0x28].to_vec();
+        ppu_run_to(&mut nes, 261, 2);
+        assert_eq!(&nes.ppu().oam()[..8], &row[..]);
+    }
+
+    #[test]
+    pub fn test_sprite_overflow_bug() {
+        // Eight sprites on lines $10-$17 and the ninth out of range. The
+        // next one is read from its tile byte, as m gets incremented too.
+        for &(tile, overflow) in [(0x10, 0x20), (0xFF, 0x00)].iter() {
+            let mut nes = ppu_nes("sprite_overflow");
+            fill_oam(&mut nes, &|n| match n {
+                0..=7 => [0x10, 0, 0, 0],
+                9 => [0xFF, tile, 0xFF, 0xFF],
+                _ => [0xFF; 4],
+            });
+            ppu_run_to(&mut nes, 241, 0);
+            ppu_write(&mut nes, 0x2001, 0x18);
+            ppu_run_to(&mut nes, 0x10, 300);
+            assert_eq!(nes.peek(W(0x2002)).0 & 0x20, overflow, "{}", tile);
+        }
+    }
+
+    #[test]
+    pub fn test_audio_samples() {
+        // A frame of CPU cycles makes 44100 / 60.1 samples, all silent
+        let mut nes = program_nes("audio_samples", &[]);
+        for _ in 0..29781 {
+            nes.cycle();
+        }
+        assert_eq!(nes.audio_samples().len(), 733);
+        assert!(nes.audio_samples().iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    pub fn test_events() {
+        let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+        nes.power_on(RamInit::Zeros);
+        nes.start_events();
+        while nes.ppu().frame_data().0 < 1 {
+            nes.cycle();
+        }
+        let position = nes.ppu().position();
+        nes.mem.store(W(0x2005), W(0x12));
+        while nes.ppu().frame_data().0 < 2 {
+            nes.cycle();
+        }
+        let events = nes.events().unwrap().frame();
+        assert!(events.iter().any(|event| {
+            event.kind == EventKind::Register(0x2005) && event.value == 0x12 &&
+            (event.scanline, event.dot) == position
+        }));
+    }
+
+    #[test]
+    pub fn test_cdl() {
+        let path = env::temp_dir().join("warnes_test.cdl");
+        let mut nes = Nes::new("test/nestest_direct.nes").unwrap();
+        nes.power_on(RamInit::Zeros);
+        nes.start_cdl(None).unwrap();
+        // The reset sequence and JMP $C5F5
+        nes.step();
+        nes.step();
+        nes.save_cdl(&path).unwrap();
+        let mut cdl = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut cdl).unwrap();
+        assert_eq!(cdl.len(), 0x4000 + 0x2000);
+        // Code at $C000-$C002 mapped in the $C000 window, vector as data
+        assert_eq!(&cdl[0..4], &[0x09, 0x09, 0x09, 0x00]);
+        assert_eq!(&cdl[0x3FFC..0x3FFE], &[0x0E, 0x0E]);
+    }
 }
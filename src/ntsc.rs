@@ -0,0 +1,246 @@
+// nes
+use ppu::{Scanline, SCANLINE_WIDTH, EMPHASIS_SHIFT};
+use palette::pack_rgb;
+// std
+use std::f32::consts::PI;
+
+// Output width for the 256 pixels, the NTSC pixel aspect ratio is kept
+pub const NTSC_WIDTH        : usize = 602;
+// A PPU dot lasts 8 master clocks, the color subcarrier 12
+const SAMPLES_PER_DOT       : usize = 8;
+const PHASES                : usize = 12;
+const LINE_SAMPLES          : usize = SCANLINE_WIDTH * SAMPLES_PER_DOT;
+// Dots in a scanline, including the ones not drawn
+const LINE_DOTS             : usize = 341;
+// Chroma is filtered over two subcarrier cycles, which bleeds colors
+const CHROMA_WINDOW         : f32 = 24.0;
+// Composite levels in volts above sync, low and high for each luma row
+const LEVELS                : [f32; 8] = [
+    0.350, 0.518, 0.962, 1.550,
+    1.094, 1.506, 1.962, 1.962,
+];
+const BLACK                 : f32 = 0.518;
+const WHITE                 : f32 = 1.962;
+const ATTENUATION           : f32 = 0.746;
+// TVs have a steeper gamma than what the image is shown with
+pub const GAMMA             : f32 = 2.2 / 1.8;
+// Decoder hue in subcarrier phases, matches the 2C02 colors
+const HUE                   : f32 = 3.9;
+
+// Both go from -1 to 1, 0 looks like a regular composite TV
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NtscSetup {
+    // Blurry to sharp, sharper leaves more chroma dots in luma
+    pub sharpness   : f32,
+    // Color fringes where luma changes
+    pub fringing    : f32,
+}
+
+pub struct Ntsc {
+    // Normalized signal of each color with emphasis at every phase
+    signal          : Vec<[f32; PHASES]>,
+    // Average of the signal above, what a perfect filter sees as luma
+    luma            : Vec<f32>,
+    // Subcarrier reference at every phase
+    phase_cos       : [f32; PHASES],
+    phase_sin       : [f32; PHASES],
+    luma_window     : f32,
+    fringing        : f32,
+    // Running sums of luma, I and Q for the scanline being decoded
+    sums            : Vec<[f32; 3]>,
+    pixels          : Vec<u32>,
+}
+
+// Level of the PPU output for a color at a subcarrier phase
+fn composite(color: usize, phase: usize) -> f32 {
+    let hue = color & 0x0F;
+    // Colors $xE and $xF are black
+    let row = if hue > 13 {1} else {(color >> 4) & 0x3};
+    let emphasis = color >> EMPHASIS_SHIFT;
+    let in_phase = |hue: usize| (hue + phase) % PHASES < 6;
+    let mut low = LEVELS[row];
+    let mut high = LEVELS[4 + row];
+    // Grays are a flat signal
+    if hue == 0 {
+        low = high;
+    } else if hue > 12 {
+        high = low;
+    }
+    let mut level = if in_phase(hue) {high} else {low};
+    // Each emphasis bit attenuates the signal during a third of the cycle
+    if (emphasis & 1 != 0 && in_phase(0)) ||
+       (emphasis & 2 != 0 && in_phase(4)) ||
+       (emphasis & 4 != 0 && in_phase(8)) {
+        level *= ATTENUATION;
+    }
+    (level - BLACK) / (WHITE - BLACK)
+}
+
+fn to_byte(value: f32, gamma: f32) -> u8 {
+    let value = if value <= 0.0 {0.0} else {value.powf(gamma)};
+    (value.min(1.0) * 255.0) as u8
+}
+
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32, gamma: f32) -> [u8; 3] {
+    [
+        to_byte(y + 0.946882 * i + 0.623557 * q, gamma),
+        to_byte(y - 0.274788 * i - 0.635691 * q, gamma),
+        to_byte(y - 1.108545 * i + 1.709007 * q, gamma),
+    ]
+}
+
+// YIQ of a color filling the screen, hue turns the decoder in degrees
+pub fn flat_color(color: usize, hue: f32) -> (f32, f32, f32) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..PHASES {
+        let level = composite(color, phase);
+        let angle = PI * (phase as f32 + HUE) / 6.0 + hue.to_radians();
+        y += level;
+        i += level * angle.cos();
+        q += level * angle.sin();
+    }
+    let phases = PHASES as f32;
+    (y / phases, i * 2.0 / phases, q * 2.0 / phases)
+}
+
+impl Ntsc {
+    pub fn new(setup: NtscSetup) -> Ntsc {
+        let colors = 1 << (EMPHASIS_SHIFT + 3);
+        let mut signal = vec![[0.0; PHASES]; colors];
+        for (color, levels) in signal.iter_mut().enumerate() {
+            for (phase, level) in levels.iter_mut().enumerate() {
+                *level = composite(color, phase);
+            }
+        }
+        let luma = signal.iter().map(|levels| {
+            levels.iter().fold(0.0, |sum, level| sum + level) / PHASES as f32
+        }).collect();
+        let mut phase_cos = [0.0; PHASES];
+        let mut phase_sin = [0.0; PHASES];
+        for phase in 0..PHASES {
+            let angle = PI * (phase as f32 + HUE) / 6.0;
+            phase_cos[phase] = angle.cos();
+            phase_sin[phase] = angle.sin();
+        }
+        let clamp = |value: f32| value.max(-1.0).min(1.0);
+        Ntsc {
+            signal          : signal,
+            luma            : luma,
+            phase_cos       : phase_cos,
+            phase_sin       : phase_sin,
+            // A full cycle removes all chroma, shorter ones keep dot crawl
+            luma_window     : PHASES as f32 - clamp(setup.sharpness) * 6.0,
+            // How much of the luma edges the chroma decoder sees
+            fringing        : (clamp(setup.fringing) + 1.0) / 2.0,
+            sums            : vec![[0.0; 3]; LINE_SAMPLES + 1],
+            pixels          : vec![0; NTSC_WIDTH],
+        }
+    }
+
+    // 0x00RRGGBB image NTSC_WIDTH pixels wide, phase is the subcarrier one
+    // at the first dot of the frame as the PPU counts it
+    pub fn filter(&mut self, frame: &[Scanline], phase: u8) -> &[u32] {
+        let height = frame.len();
+        self.pixels.resize(NTSC_WIDTH * height, 0);
+        for (y, line) in frame.iter().enumerate() {
+            // Pixels start at dot 1
+            let phase = (phase as usize +
+                         (y * LINE_DOTS + 1) * SAMPLES_PER_DOT) % PHASES;
+            self.decode_line(line, phase, y);
+        }
+        &self.pixels
+    }
+
+    fn decode_line(&mut self, line: &Scanline, first_phase: usize, y: usize) {
+        let mut sum = [0.0; 3];
+        for sample in 0..LINE_SAMPLES {
+            let color = line[sample / SAMPLES_PER_DOT] as usize;
+            let phase = (first_phase + sample) % PHASES;
+            let level = self.signal[color][phase];
+            let chroma = level - (1.0 - self.fringing) * self.luma[color];
+            sum[0] += level;
+            sum[1] += chroma * self.phase_cos[phase];
+            sum[2] += chroma * self.phase_sin[phase];
+            self.sums[sample + 1] = sum;
+        }
+        let row = y * NTSC_WIDTH;
+        for x in 0..NTSC_WIDTH {
+            let center = (x as f32 + 0.5) * LINE_SAMPLES as f32 /
+                         NTSC_WIDTH as f32;
+            let luma = self.average(center, self.luma_window, 0);
+            // The reference averages to one half over a cycle
+            let i = self.average(center, CHROMA_WINDOW, 1) * 2.0;
+            let q = self.average(center, CHROMA_WINDOW, 2) * 2.0;
+            self.pixels[row + x] = pack_rgb(&yiq_to_rgb(luma, i, q, GAMMA));
+        }
+    }
+
+    // Box filter of the running sums, clamped to the scanline
+    fn average(&self, center: f32, window: f32, index: usize) -> f32 {
+        let half = window / 2.0;
+        let start = (center - half).max(0.0) as usize;
+        let end = ((center + half) as usize).min(LINE_SAMPLES);
+        (self.sums[end][index] - self.sums[start][index]) /
+            (end - start) as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ntsc::*;
+    use ppu::{Scanline, SCANLINE_WIDTH};
+    use palette::pack_rgb;
+
+    fn frame(colors: &dyn Fn(usize) -> u16) -> Vec<Scanline> {
+        let mut line = Scanline([0; SCANLINE_WIDTH]);
+        for (x, color) in line.0.iter_mut().enumerate() {
+            *color = colors(x);
+        }
+        vec![line; 2]
+    }
+
+    fn channels(pixel: u32) -> [i32; 3] {
+        [(pixel >> 16) as i32 & 0xFF, (pixel >> 8) as i32 & 0xFF,
+         pixel as i32 & 0xFF]
+    }
+
+    // Pixels of a black to white edge that aren't either side, without
+    // fringing grays have no chroma to blur it further
+    fn edge_width(sharpness: f32) -> usize {
+        let mut ntsc = Ntsc::new(NtscSetup {sharpness: sharpness,
+                                            fringing: -1.0});
+        let pixels = ntsc.filter(&frame(&|x| if x < 128 {0x0F} else {0x30}),
+                                 0);
+        let (black, white) = (pixels[0], pixels[NTSC_WIDTH - 1]);
+        pixels[..NTSC_WIDTH].iter()
+                            .filter(|&&pixel| pixel != black && pixel != white)
+                            .count()
+    }
+
+    #[test]
+    pub fn test_flat_color() {
+        let mut ntsc = Ntsc::new(Default::default());
+        for &color in [0x00, 0x16, 0x21, 0x2A, 0x30, 0x16 | 5 << 6].iter() {
+            let (y, i, q) = flat_color(color as usize, 0.0);
+            let expected = channels(pack_rgb(&yiq_to_rgb(y, i, q, GAMMA)));
+            // Any subcarrier phase, away from the ends of the line
+            for phase in 0..12 {
+                let pixels = ntsc.filter(&frame(&|_| color), phase);
+                for &pixel in &pixels[10..NTSC_WIDTH - 10] {
+                    let decoded = channels(pixel);
+                    for c in 0..3 {
+                        assert!((decoded[c] - expected[c]).abs() <= 1,
+                                "color {:03X} phase {}: {:?} {:?}", color,
+                                phase, decoded, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_sharpness() {
+        assert!(edge_width(1.0) < edge_width(0.0));
+        assert!(edge_width(0.0) < edge_width(-1.0));
+    }
+}
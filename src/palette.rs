@@ -0,0 +1,204 @@
+// nes
+use ppu::EMPHASIS_SHIFT;
+use ntsc::{flat_color, yiq_to_rgb, GAMMA};
+// std
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+const BASE_COLORS           : usize = 0x40;
+// Every color with the 8 combinations of emphasis bits
+const PALETTE_COLORS        : usize = BASE_COLORS << 3;
+const COLOR_BYTES           : usize = 3;
+// Color channels not emphasized get darker, for each emphasized one
+const EMPHASIS_ATTENUATION  : f32 = 0.816328;
+// RGB NES Palette
+const DEFAULT_PALETTE : [[u8; 3]; BASE_COLORS] = [
+    [ 84, 84, 84], [  0, 30,116], [  8, 16,144], [ 48,  0,136],
+    [ 68,  0,100], [ 92,  0, 48], [ 84,  4,  0], [ 60, 24,  0],
+    [ 32, 42,  0], [  8, 58,  0], [  0, 64,  0], [  0, 60,  0],
+    [  0, 50, 60], [  0,  0,  0], [  0,  0,  0], [  0,  0,  0],
+    [152,150,152], [  8, 76,196], [ 48, 50,236], [ 92, 30,228],
+    [136, 20,176], [160, 20,100], [152, 34, 32], [120, 60,  0],
+    [ 84, 90,  0], [ 40,114,  0], [  8,124,  0], [  0,118, 40],
+    [  0,102,120], [  0,  0,  0], [  0,  0,  0], [  0,  0,  0],
+    [236,238,236], [ 76,154,236], [120,124,236], [176, 98,236],
+    [228, 84,236], [236, 88,180], [236,106,100], [212,136, 32],
+    [160,170,  0], [116,196,  0], [ 76,208, 32], [ 56,204,108],
+    [ 56,180,204], [ 60, 60, 60], [  0,  0,  0], [  0,  0,  0],
+    [236,238,236], [168,204,236], [188,188,236], [212,178,236],
+    [236,174,236], [236,174,212], [236,180,176], [228,196,144],
+    [204,210,120], [180,222,120], [168,226,144], [152,226,180],
+    [160,214,228], [160,162,160], [  0,  0,  0], [  0,  0,  0],
+];
+
+// Knobs of the generated palette, the defaults match the NTSC filter
+#[derive(Debug, Clone, Copy)]
+pub struct PaletteSetup {
+    // Decoder hue rotation in degrees
+    pub hue         : f32,
+    pub saturation  : f32,
+    pub contrast    : f32,
+    pub brightness  : f32,
+    // Exponent from the signal to the screen
+    pub gamma       : f32,
+}
+
+impl Default for PaletteSetup {
+    fn default() -> PaletteSetup {
+        PaletteSetup {
+            hue         : 0.0,
+            saturation  : 1.0,
+            contrast    : 1.0,
+            brightness  : 0.0,
+            gamma       : GAMMA,
+        }
+    }
+}
+
+// RGB of every color the PPU outputs, emphasis bits above the color index
+pub struct Palette {
+    colors          : Box<[[u8; 3]]>,
+    // The same colors as 0x00RRGGBB
+    packed          : Box<[u32]>,
+}
+
+pub fn pack_rgb(rgb: &[u8; 3]) -> u32 {
+    (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::from_base(&DEFAULT_PALETTE)
+    }
+}
+
+impl Palette {
+    // Expands the 64 colors into the 8 emphasis variants of each. A channel
+    // dims once per other emphasized bit, so two bits still tint towards
+    // their mix and all three darken every channel by the square.
+    fn from_base(base: &[[u8; 3]]) -> Palette {
+        let mut colors = vec![[0u8; 3]; PALETTE_COLORS];
+        for (index, color) in colors.iter_mut().enumerate() {
+            let emphasis = index >> EMPHASIS_SHIFT;
+            *color = base[index % BASE_COLORS];
+            for channel in 0..3 {
+                let others = emphasis & !(1 << channel);
+                for bit in 0..3 {
+                    if others & 1 << bit != 0 {
+                        color[channel] = (color[channel] as f32 *
+                                          EMPHASIS_ATTENUATION) as u8;
+                    }
+                }
+            }
+        }
+        Palette::new(colors)
+    }
+
+    fn new(colors: Vec<[u8; 3]>) -> Palette {
+        Palette {
+            packed          : colors.iter().map(pack_rgb).collect::<Vec<_>>()
+                                    .into_boxed_slice(),
+            colors          : colors.into_boxed_slice(),
+        }
+    }
+
+    // 64 color files get the emphasis computed, 512 color ones have it
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, String> {
+        let mut file = try_err!(File::open(path), "Couldn't open palette file");
+        let mut data = Vec::new();
+        try_err!(file.read_to_end(&mut data), "Couldn't read palette file");
+        if data.len() != BASE_COLORS * COLOR_BYTES &&
+           data.len() != PALETTE_COLORS * COLOR_BYTES {
+            return err!("Invalid palette size {}, expected {} or {} bytes",
+                        data.len(), BASE_COLORS * COLOR_BYTES,
+                        PALETTE_COLORS * COLOR_BYTES);
+        }
+        let colors: Vec<[u8; 3]> = data.chunks(COLOR_BYTES)
+                                       .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                                       .collect();
+        if colors.len() == BASE_COLORS {
+            Ok(Palette::from_base(&colors))
+        } else {
+            Ok(Palette::new(colors))
+        }
+    }
+
+    // Always the 512 color format
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let mut file = try_err!(File::create(path),
+                                "Couldn't create palette file");
+        for color in self.colors.iter() {
+            try_err!(file.write_all(color), "Couldn't write palette file");
+        }
+        Ok(())
+    }
+
+    // Decodes the composite signal of each color as a TV with the setup would
+    pub fn generate(setup: PaletteSetup) -> Palette {
+        let colors: Vec<[u8; 3]> = (0..PALETTE_COLORS).map(|color| {
+            let (y, i, q) = flat_color(color, setup.hue);
+            let chroma = setup.saturation * setup.contrast;
+            yiq_to_rgb(y * setup.contrast + setup.brightness, i * chroma,
+                       q * chroma, setup.gamma)
+        }).collect();
+        Palette::new(colors)
+    }
+
+    pub fn packed(&self, color: u16) -> u32 {
+        self.packed[color as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use palette::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+
+    fn write_file(name: &str, data: &[u8]) -> ::std::path::PathBuf {
+        let path = env::temp_dir().join(format!("warnes_test_{}.pal", name));
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    #[test]
+    pub fn test_save_load() {
+        let path = env::temp_dir().join("warnes_test_save.pal");
+        let palette = Palette::generate(Default::default());
+        palette.save(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 512 * 3);
+        let loaded = Palette::load(&path).unwrap();
+        assert_eq!(loaded.colors, palette.colors);
+        assert_eq!(loaded.packed, palette.packed);
+    }
+
+    #[test]
+    pub fn test_base_palette() {
+        let data: Vec<u8> = (0..64 * 3).map(|n| (n % 3) as u8 * 50 + 100)
+                                       .collect();
+        let palette = Palette::load(write_file("base", &data)).unwrap();
+        assert_eq!(palette.colors.len(), 512);
+        let scale = |value: u8, times: usize| (0..times).fold(value, |v, _| {
+            (v as f32 * EMPHASIS_ATTENUATION) as u8
+        });
+        // No emphasis, red, red and green, then all three
+        assert_eq!(palette.colors[0x3F], [100, 150, 200]);
+        assert_eq!(palette.colors[0x3F | 1 << EMPHASIS_SHIFT],
+                   [100, scale(150, 1), scale(200, 1)]);
+        assert_eq!(palette.colors[0x3F | 3 << EMPHASIS_SHIFT],
+                   [scale(100, 1), scale(150, 1), scale(200, 2)]);
+        assert_eq!(palette.colors[0x3F | 7 << EMPHASIS_SHIFT],
+                   [scale(100, 2), scale(150, 2), scale(200, 2)]);
+        assert_eq!(palette.packed(0x3F), 0x6496C8);
+    }
+
+    #[test]
+    pub fn test_invalid_size() {
+        for &size in [0, 63 * 3, 64 * 3 + 1, 512 * 3 - 3].iter() {
+            let path = write_file("invalid", &vec![0; size]);
+            assert!(Palette::load(path).is_err(), "size {}", size);
+        }
+    }
+}
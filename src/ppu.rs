@@ -1,12 +1,11 @@
-extern crate sdl2;
-
 // NES
 use utils::*;
 use mem::{Memory as Mem};
-use enums::{MemState, Interrupt};
+use enums::{MemState, Region};
 use scroll::Scroll;
 
 // std
+use std::cmp;
 use std::fmt;
 use std::num::Wrapping as W;
 use std::ops::{Index, IndexMut};
@@ -22,9 +21,16 @@ macro_rules! tile_bit {
 }
 
 const CTRL_SPRITE_PATTERN       : u8 = 0x08;
+const CTRL_BACKGROUND_PATTERN   : u8 = 0x10;
 const CTRL_NMI                  : u8 = 0x80;
 
+const MASK_GRAYSCALE            : u8 = 0x01;
+const MASK_LEFT_BACKGROUND      : u8 = 0x02;
+const MASK_LEFT_SPRITES         : u8 = 0x04;
+const MASK_EMPHASIS_SHIFT       : u8 = 5;
+
 const STATUS_SPRITE_OVERFLOW    : u8 = 0x20;
+const STATUS_POWER_ON           : u8 = 0xA0;
 const STATUS_SPRITE_0_HIT       : u8 = 0x40;
 const STATUS_VBLANK             : u8 = 0x80;
 
@@ -34,13 +40,23 @@ const PALETTE_ADDRESS           : usize = 0x3f00;
 // Resolution
 pub const SCANLINE_WIDTH        : usize = 256;
 pub const SCANLINE_COUNT        : usize = 240;
+// Pixels are a 6 bit palette color with the 3 emphasis bits above it
+pub const EMPHASIS_SHIFT        : u8 = 6;
+// NTSC master clocks per dot and per color subcarrier cycle
+const DOT_CLOCKS                : u8 = 8;
+const SUBCARRIER_CLOCKS         : u8 = 12;
+// Left column that PPUMASK can hide
+const LEFT_CLIP_WIDTH           : usize = 8;
+// Sprites per scanline the hardware renders and sprites in OAM
+const SPRITE_UNITS              : usize = 8;
+const OAM_SPRITES               : usize = 64;
 
 // TODO: Wait for arbitrary size array default impls to remove Scanline
-pub struct Scanline(pub [u8; SCANLINE_WIDTH]);
+pub struct Scanline(pub [u16; SCANLINE_WIDTH]);
 
 impl Scanline {
     fn new() -> Scanline {
-        Scanline([0u8; SCANLINE_WIDTH])
+        Scanline([0u16; SCANLINE_WIDTH])
     }
 }
 
@@ -51,15 +67,15 @@ impl Clone for Scanline {
 }
 
 impl Index<usize> for Scanline {
-    type Output = u8;
+    type Output = u16;
 
-    fn index(&self, index: usize) -> &u8 {
+    fn index(&self, index: usize) -> &u16 {
         &self.0[index]
     }
 }
 
 impl IndexMut<usize> for Scanline {
-    fn index_mut(&mut self, index: usize) -> &mut u8 {
+    fn index_mut(&mut self, index: usize) -> &mut u16 {
         &mut self.0[index]
     }
 }
@@ -71,6 +87,21 @@ pub struct PpuReadRegs {
     pub status  : u8,
 }
 
+// Display overrides for debugging, emulation runs as if they weren't set
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct DebugLayers {
+    pub hide_background : bool,
+    pub hide_sprites    : bool,
+    // One bit per OAM entry
+    pub hidden_sprites  : u64,
+}
+
+impl DebugLayers {
+    pub fn sprite_hidden(&self, index: usize) -> bool {
+        self.hide_sprites || self.hidden_sprites & 1 << index != 0
+    }
+}
+
 pub struct Ppu {
     palette         : [u8; PALETTE_SIZE],
     oam             : Oam,
@@ -80,15 +111,28 @@ pub struct Ppu {
     mask            : u8,
     status          : u8,
     data_buffer     : u8,
-    // Scanline should count up until the total numbers of scanlines (262)
+    region          : Region,
+    layers          : DebugLayers,
+    // Rendering as the fetch logic sees it, it follows PPUMASK a dot late
+    render_enabled  : bool,
+    // Scanline should count up until the total numbers of scanlines
     scanline        : usize,
     // Each scanline has 341 cycles
     scycle          : usize,
     cycles          : u32,
-    sprites         : [Sprite; 0x08],
+    // Units past the eighth are only used without the sprite limit
+    sprites         : [Sprite; OAM_SPRITES],
     background      : Background,
     frames          : u64,
     frame_data      : Box<[Scanline]>,
+    // Subcarrier phase of the next dot in master clocks, the one at the
+    // start of the frame being drawn and of the last complete one
+    phase           : u8,
+    start_phase     : u8,
+    frame_phase     : u8,
+    // Writes to ctrl, mask, scroll and address are ignored until the end of
+    // the first vblank after power on or reset
+    warm_up         : bool,
 }
 
 
@@ -101,50 +145,103 @@ impl Ppu {
 
             ctrl            : 0,
             mask            : 0,
-            status          : 0,
+            // VBlank and sprite overflow are usually set at power on
+            status          : STATUS_POWER_ON,
             data_buffer     : 0,
 
+            region          : Region::Ntsc,
+            layers          : DebugLayers::default(),
+            render_enabled  : false,
             scanline        : 0,
             scycle          : 0,
             cycles          : 0,
 
-            sprites         : [Sprite::default(); 8],
+            sprites         : [Sprite::default(); OAM_SPRITES],
             background      : Background::default(),
 
             frames          : 0,
             frame_data      : vec![Scanline::new(); SCANLINE_COUNT]
                                   .into_boxed_slice(),
+            phase           : 0,
+            start_phase     : 0,
+            frame_phase     : 0,
+            warm_up         : true,
         }
     }
 
+    pub fn power_on(&mut self) {
+        let (region, layers) = (self.region, self.layers);
+        let sprite_limit = self.oam.sprite_limit;
+        *self = Ppu::new();
+        self.region = region;
+        self.layers = layers;
+        self.oam.sprite_limit = sprite_limit;
+    }
+
+    pub fn set_debug_layers(&mut self, layers: DebugLayers) {
+        self.layers = layers;
+    }
+
+    pub fn debug_layers(&self) -> DebugLayers {
+        self.layers
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // Without the limit every sprite in range is drawn, the sprite overflow
+    // flag and evaluation timing are still the hardware ones
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.oam.sprite_limit = limit;
+    }
+
+    // OAM, palette, OAMADDR, the VRAM address and vblank are kept
+    pub fn soft_reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.data_buffer = 0;
+        self.address.soft_reset();
+        self.warm_up = true;
+    }
+
     pub fn cycle(&mut self, memory: &mut Mem) {
         // Update PPU with what the CPU hay have sent to memory latch
         self.ls_latches(memory);
+        let prerender = self.prerender_line();
         if self.render_on() {
             match (self.scycle, self.scanline) {
                 // Idle scanlines
-                (_, 240...260) => (),
+                (_, line) if line >= 240 && line != prerender => (),
                 // Last scanline, updates vertical scroll
-                (280...304, 261) => self.address.copy_vertical(),
+                (280...304, line) if line == prerender =>
+                    self.address.copy_vertical(),
                 // Dot 257 updates horizontal scroll
                 (257, _) => self.address.copy_horizontal(),
                 // Overlaps with above but nothing really happens in 257
                 (257...320, _) => {
                     // This syncs with sprite evaluation in oam
                     self.fetch_sprite(memory);
+                    if self.scycle == 320 {
+                        self.fetch_extra_sprites(memory);
+                    }
                 }
                 // At dot 1 of prerender we need to unset the sprite bits
-                (1, 261) => self.status &= !(STATUS_SPRITE_0_HIT |
-                                             STATUS_SPRITE_OVERFLOW),
+                (1, line) if line == prerender => {
+                    self.status &= !(STATUS_SPRITE_0_HIT |
+                                     STATUS_SPRITE_OVERFLOW);
+                    self.oam.corrupt();
+                },
                 // Idle cycles
                 (0, _) | (337...340, _) => (),
                 _ => {
                     // These are fetching scanlines, including prerender
-                    // (1...256 + 321..336, 0...239 + 261)
-                    if self.scycle < 257 && self.scanline != 261 {
+                    // (1...256 + 321..336, 0...239 + prerender)
+                    if self.scycle < 257 && self.scanline != prerender {
                         self.draw_dot();
                         // Decrement sprite counters or shift their tile data
-                        for s in self.sprites.iter_mut() {
+                        let count = self.oam.count();
+                        for s in self.sprites[..count].iter_mut() {
                             s.decrement_or_shift();
                         }
                     }
@@ -166,27 +263,36 @@ impl Ppu {
                               &mut self.sprites, big_sprites) {
                 set_flag!(self.status, STATUS_SPRITE_OVERFLOW);
             }
+            // OAMADDR is cleared while sprite tiles are fetched
+            if self.rendering() && self.scycle >= 257 && self.scycle <= 320 {
+                self.oam.set_address(W(0));
+            }
+        } else if self.scanline < 240 && self.scycle >= 1 && self.scycle <= 256 {
+            self.draw_backdrop();
         }
+        self.address.cycle();
+        self.render_enabled = self.show_sprites() || self.show_background();
         // VBLANK
-        if self.scycle == 1 && self.scanline == 241 {
+        if self.scycle == 1 && self.scanline == self.region.vblank_line() {
             set_flag!(self.status, STATUS_VBLANK);
-            if is_flag_set!(self.ctrl, CTRL_NMI) {
-                memory.set_interrupt(Interrupt::NMI);
-            }
-        } else if self.scycle == 1 && self.scanline == 261 {
+        } else if self.scycle == 1 && self.scanline == prerender {
             unset_flag!(self.status, STATUS_VBLANK);
+            self.warm_up = false;
         }
-        // TODO
-        // When render is not activated the loop is shorter
-        // if !self.render_on() && self.cycles == VBLANK_END_NO_RENDER {}
-
+        // With rendering on odd frames jump from dot 339 of prerender to the
+        // next frame, PPUMASK writes seen up to this dot count
+        let skip = self.scycle == 339 && self.scanline == prerender &&
+                   self.frames & 1 == 1 && self.render_on() &&
+                   self.region.skips_odd_dot();
+        self.phase = (self.phase + DOT_CLOCKS) % SUBCARRIER_CLOCKS;
         // Reset values at the end of scanlines
-        if self.scycle == 340 && self.scanline == 261 {
-            // TODO: Skip a cycle on odd frames and background on
+        if (self.scycle == 340 && self.scanline == prerender) || skip {
             self.scycle = 0;
             self.scanline = 0;
             self.cycles = 0;
             self.frames += 1;
+            self.frame_phase = self.start_phase;
+            self.start_phase = self.phase;
         } else if self.scycle == 340 {
             // If we finished the current scanline we pass to the next one
             self.scanline += 1;
@@ -197,85 +303,130 @@ impl Ppu {
         }
         let read_regs = PpuReadRegs {
                 data    : self.data_buffer,
-                oam     : self.oam.load_data(),
+                oam     : self.oam.load_data(self.rendering()),
                 status  : self.status,
         };
         // Update memory PPU registers copy
         memory.set_ppu_read_regs(read_regs);
+        // NMI is held while in vblank with NMI enabled, the CPU sees the edge
+        memory.set_nmi(is_flag_set!(self.status, STATUS_VBLANK) &&
+                       is_flag_set!(self.ctrl, CTRL_NMI));
     }
 
     fn fetch_sprite(&mut self, memory: &mut Mem) {
         let big_sprites = self.sprite_big();
         let table = self.sprite_table();
-        let sprite = &mut self.sprites[((self.scycle - 1) / 8) % 8];
-        // Get fine Y position
-        let mut y_offset = W16!(W(self.scanline as u8) - sprite.y_pos);
-        if sprite.flip_vertically() {
-            y_offset = W(if big_sprites {15} else {7}) - y_offset;
-        }
-        // With big sprites we need to jump to the next tile
-        if y_offset >= W(8) {
-            y_offset += W(8)
-        }
-        // Compose the table and the tile address with the fine Y position
-        let address = if big_sprites {
-            (W16!(W(sprite.tile.0.rotate_right(1))) << 5) | y_offset
-        } else {
-            table | (W16!(sprite.tile) << 4) | y_offset
-        };
+        let scanline = self.scanline;
+        let sprite = &mut self.sprites[((self.scycle - 1) / 8) % SPRITE_UNITS];
+        let address = sprite.pattern_address(scanline, big_sprites, table);
         match (self.scycle - 1) % 8 {
             3 => sprite.latch = sprite.attributes.0,
             4 => sprite.counter = sprite.x_pos.0,
-            5 => {
-                sprite.lshift = memory.chr_load(address).0;
-                if !sprite.flip_horizontally() {
-                    sprite.lshift = reverse_byte(sprite.lshift);
-                }
-            },
-            7 => {
-                sprite.hshift = memory.chr_load(address + W(8)).0;
-                if !sprite.flip_horizontally() {
-                    sprite.hshift = reverse_byte(sprite.hshift);
-                }
-            },
+            5 => sprite.lshift = sprite.pattern_row(memory.chr_load(address)),
+            7 => sprite.hshift =
+                    sprite.pattern_row(memory.chr_load(address + W(8))),
             _ => {},
         }
     }
 
+    // The hardware never fetches the sprites past the eighth, read their
+    // patterns without side effects on the mapper
+    fn fetch_extra_sprites(&mut self, memory: &Mem) {
+        let big_sprites = self.sprite_big();
+        let table = self.sprite_table();
+        let scanline = self.scanline;
+        let count = self.oam.count();
+        for sprite in self.sprites.iter_mut().take(count).skip(SPRITE_UNITS) {
+            let address = sprite.pattern_address(scanline, big_sprites, table);
+            sprite.latch = sprite.attributes.0;
+            sprite.counter = sprite.x_pos.0;
+            sprite.lshift = sprite.pattern_row(memory.chr_peek(address));
+            sprite.hshift = sprite.pattern_row(memory.chr_peek(address + W(8)));
+        }
+    }
+
     fn draw_dot(&mut self) {
+        let x = self.scycle - 1;
+        let left = x < LEFT_CLIP_WIDTH;
         let mut back_index = 0;
-        if self.show_background() {
+        if self.show_background() &&
+           (!left || is_flag_set!(self.mask, MASK_LEFT_BACKGROUND)) {
             let fine_x = self.address.get_fine_x();
             back_index = self.background.get_palette_index(fine_x);
         }
+        // Sprite 0 hit sees the real layers, the debug ones are only shown
+        let layers = self.layers;
+        let shown_back = if layers.hide_background {0} else {back_index};
         // Assume we are going to draw the background or the back color
-        let mut color_index = self.palette[back_index];
-        if self.show_sprites() {
+        let mut color_index = self.palette[shown_back];
+        if self.show_sprites() &&
+           (!left || is_flag_set!(self.mask, MASK_LEFT_SPRITES)) {
             // Amount of sprites in this scanline
             let cnt = self.oam.count();
             // Look for the first sprite that has a pixel to draw
             let index = self.sprites[..cnt].iter().position(Sprite::has_pixel);
-            if let Some(index) = index {
-                let sprite = &self.sprites[index];
-                let sprite_front = sprite.get_priority();
-                // We have a sprite pixel, we should check for sprite 0 hit
-                if self.oam.sprite_zero_hit() && index == 0 &&
-                   back_index != 0 && self.scycle != 256 {
-                    self.status |= STATUS_SPRITE_0_HIT;
-                }
-                if sprite_front || back_index == 0 {
+            // We have a sprite pixel, we should check for sprite 0 hit
+            if index == Some(0) && self.oam.sprite_zero_hit() &&
+               back_index != 0 && self.scycle != 256 {
+                self.status |= STATUS_SPRITE_0_HIT;
+            }
+            // Hidden sprites let the ones below them show
+            let sprite = self.sprites[..cnt].iter().find(|s| {
+                s.has_pixel() && !layers.sprite_hidden(s.index as usize)
+            });
+            if let Some(sprite) = sprite {
+                if sprite.get_priority() || shown_back == 0 {
                     color_index = self.palette[sprite.get_palette_index()];
                 }
             }
         }
-        self.frame_data[self.scanline][self.scycle - 1] = color_index;
+        self.put_pixel(x, color_index);
     }
 
-    fn sprite_big(&self) -> bool {
+    // With rendering off the backdrop is shown, unless the address points
+    // to the palettes, then that color is
+    fn draw_backdrop(&mut self) {
+        let address = self.address.peek_address().0 as usize;
+        let index = if address >= PALETTE_ADDRESS {
+            self.palette_mirror(address)
+        } else {
+            0
+        };
+        let color_index = self.palette[index];
+        self.put_pixel(self.scycle - 1, color_index);
+    }
+
+    fn put_pixel(&mut self, x: usize, mut color_index: u8) {
+        if is_flag_set!(self.mask, MASK_GRAYSCALE) {
+            color_index &= 0x30;
+        }
+        self.frame_data[self.scanline][x] = color_index as u16 |
+                                            self.emphasis() << EMPHASIS_SHIFT;
+    }
+
+    // Emphasis bits as red, green and blue, the 2C07 swaps red and green
+    fn emphasis(&self) -> u16 {
+        let bits = (self.mask >> MASK_EMPHASIS_SHIFT) as u16;
+        match self.region {
+            Region::Ntsc => bits,
+            Region::Pal | Region::Dendy =>
+                bits & 0x4 | (bits & 0x1) << 1 | (bits & 0x2) >> 1,
+        }
+    }
+
+    pub fn sprite_big(&self) -> bool {
         is_flag_set!(self.ctrl, 0x20)
     }
 
-    fn sprite_table(&self) -> W<u16> {
+    pub fn background_table(&self) -> W<u16> {
+        if is_flag_set!(self.ctrl, CTRL_BACKGROUND_PATTERN) {
+            W(0x1000)
+        } else {
+            W(0)
+        }
+    }
+
+    pub fn sprite_table(&self) -> W<u16> {
         if is_flag_set!(self.ctrl, CTRL_SPRITE_PATTERN) {
             W(0x1000)
         } else {
@@ -284,11 +435,16 @@ impl Ppu {
     }
 
     fn render_on(&self) -> bool {
-        self.show_sprites() || self.show_background()
+        self.render_enabled
     }
 
     fn rendering(&self) -> bool {
-        self.render_on() && (self.scanline < 240 || self.scanline == 261)
+        self.render_on() &&
+        (self.scanline < 240 || self.scanline == self.prerender_line())
+    }
+
+    fn prerender_line(&self) -> usize {
+        self.region.scanlines() - 1
     }
 
     fn show_sprites(&self) -> bool {
@@ -302,19 +458,22 @@ impl Ppu {
     /* load store latches */
     fn ls_latches(&mut self, memory: &mut Mem) {
         let (latch, status) = memory.get_latch();
-        match status {
+        let ignored = match status {
+            MemState::PpuCtrl | MemState::PpuMask |
+            MemState::PpuScroll | MemState::PpuAddr => self.warm_up,
+            _ => false,
+        };
+        match if ignored {MemState::NoState} else {status} {
             MemState::PpuCtrl   => {
-                if !is_flag_set!(self.ctrl, CTRL_NMI) &&
-                    is_flag_set!(latch.0, CTRL_NMI) &&
-                    is_flag_set!(self.status, STATUS_VBLANK) {
-                    memory.set_interrupt(Interrupt::NMI);
-                }
                 self.ctrl = latch.0;
                 self.address.set_ppuctrl(latch);
             },
             MemState::PpuMask   => { self.mask = latch.0; },
             MemState::OamAddr   => { self.oam.set_address(latch); },
-            MemState::OamData   => { self.oam.store_data(latch); },
+            MemState::OamData   => {
+                let rendering = self.rendering();
+                self.oam.store_data(latch, rendering);
+            },
             MemState::PpuScroll => { self.address.set_scroll(latch); },
             MemState::PpuAddr   => { self.address.set_address(latch); },
             MemState::PpuData   => { self.store(memory, latch);},
@@ -335,7 +494,7 @@ impl Ppu {
         }
     }
 
-    fn palette_mirror(&mut self, address: usize) -> usize {
+    fn palette_mirror(&self, address: usize) -> usize {
         let index = address & (PALETTE_SIZE - 1);
         // Mirroring 0x10/0x14/0x18/0x1C to lower address
         if (index & 0x3) == 0 {
@@ -345,12 +504,23 @@ impl Ppu {
         }
     }
 
+    // Reads any PPU address without side effects
+    pub fn peek(&self, memory: &Mem, address: W<u16>) -> W<u8> {
+        let address = address & W(0x3FFF);
+        let addr = address.0 as usize;
+        if addr < PALETTE_ADDRESS {
+            memory.chr_peek(address)
+        } else {
+            W(self.palette[self.palette_mirror(addr)])
+        }
+    }
+
     fn load(&mut self, memory: &mut Mem) -> W<u8> {
         let rendering = self.rendering();
         let address = self.address.get_address(rendering);
         let addr = address.0 as usize;
         if addr < PALETTE_ADDRESS {
-            memory.chr_load(address)
+            memory.chr_read(address)
         } else {
             W(self.palette[self.palette_mirror(addr)])
         }
@@ -367,9 +537,33 @@ impl Ppu {
         }
     }
 
+    // Scanline and dot about to be rendered
+    pub fn position(&self) -> (usize, usize) {
+        (self.scanline, self.scycle)
+    }
+
     pub fn frame_data(&self) -> (u64, &[Scanline]) {
         (self.frames, &self.frame_data)
     }
+
+    // Where the last complete frame started in the color subcarrier, it
+    // moves every frame, by less when the odd frame dot is skipped
+    pub fn frame_phase(&self) -> u8 {
+        self.frame_phase
+    }
+
+    pub fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam.mem
+    }
+
+    // Top left corner of the screen in the four nametables
+    pub fn scroll_position(&self) -> (usize, usize) {
+        self.address.get_position()
+    }
 }
 
 impl Default for Ppu {
@@ -458,6 +652,8 @@ impl Background {
 
 #[derive(Copy, Clone, Default)]
 struct Sprite {
+    // OAM entry the sprite came from
+    pub index       : u8,
     pub y_pos       : W<u8>,
     pub tile        : W<u8>,
     pub attributes  : W<u8>,
@@ -480,6 +676,35 @@ impl Sprite {
         }
     }
 
+    // Address of the sprite pattern row for the scanline
+    pub fn pattern_address(&self, scanline: usize, big_sprites: bool,
+                           table: W<u16>) -> W<u16> {
+        // Get fine Y position
+        let mut y_offset = W16!(W(scanline as u8) - self.y_pos);
+        if self.flip_vertically() {
+            y_offset = W(if big_sprites {15} else {7}) - y_offset;
+        }
+        // With big sprites we need to jump to the next tile
+        if y_offset >= W(8) {
+            y_offset += W(8)
+        }
+        // Compose the table and the tile address with the fine Y position
+        if big_sprites {
+            (W16!(W(self.tile.0.rotate_right(1))) << 5) | y_offset
+        } else {
+            table | (W16!(self.tile) << 4) | y_offset
+        }
+    }
+
+    // Pattern bits in the order they are shifted out
+    pub fn pattern_row(&self, value: W<u8>) -> u8 {
+        if self.flip_horizontally() {
+            value.0
+        } else {
+            reverse_byte(value.0)
+        }
+    }
+
     pub fn get_palette_index(&self) -> usize {
         let sprite_index = (self.lshift & 1) | ((self.hshift & 1) << 1);
         (self.get_palette() + 4) * 4 + sprite_index as usize
@@ -518,12 +743,20 @@ impl Sprite {
 struct Oam {
     mem             : [u8; 0x100],
     smem            : [u8; 0x20],
-    mem_index       : W<u8>,
+    // OAM entries copied to secondary OAM
+    sindex          : [u8; SPRITE_UNITS],
     smem_index      : usize,
+    // OAMADDR, evaluation uses it to walk OAM as sprite n in the high 6 bits
+    // and byte m in the low 2 bits
     address         : W<u8>,
     count           : usize,
+    // Last value on the OAM bus, $2004 returns it while rendering
     read            : u8,
-    next_sprite     : bool,
+    // All 64 sprites were checked or the overflow was found
+    eval_done       : bool,
+    // First sprite evaluated after secondary OAM filled up
+    extra_from      : Option<usize>,
+    sprite_limit    : bool,
     zero_hit_now    : bool,
     zero_hit_next   : bool,
 }
@@ -533,12 +766,14 @@ impl Default for Oam {
         Oam {
             mem             : [0; 0x100],
             smem            : [0; 0x20],
-            mem_index       : W(0),
+            sindex          : [0; SPRITE_UNITS],
             smem_index      : 0,
             address         : W(0),
             count           : 0,
             read            : 0,
-            next_sprite     : false,
+            eval_done       : false,
+            extra_from      : None,
+            sprite_limit    : true,
             zero_hit_now    : false,
             zero_hit_next   : false,
         }
@@ -555,12 +790,23 @@ impl fmt::Debug for Oam {
 
 impl Oam {
 
-    fn load_data(&mut self) -> u8 {
-        self.mem[self.address.0 as usize]
+    fn load_data(&self, rendering: bool) -> u8 {
+        if rendering {
+            self.read
+        } else {
+            self.mem[self.address.0 as usize]
+        }
     }
 
-    fn store_data(&mut self, value: W<u8>) {
-        self.mem[self.address.0 as usize] = value.0;
+    fn store_data(&mut self, value: W<u8>, rendering: bool) {
+        if rendering {
+            // The write is lost and only the sprite index is incremented
+            self.address += W(4);
+            return;
+        }
+        // Attribute bits 2-4 don't exist and read back as 0
+        let value = if self.address.0 & 3 == 2 {value.0 & 0xE3} else {value.0};
+        self.mem[self.address.0 as usize] = value;
         self.address += W(1);
     }
 
@@ -568,6 +814,17 @@ impl Oam {
         self.address = addr;
     }
 
+    // Rendering starting with OAMADDR at 8 or more copies the eight bytes
+    // at OAMADDR & $F8 over the first eight
+    fn corrupt(&mut self) {
+        let row = (self.address.0 & 0xF8) as usize;
+        if row != 0 {
+            for i in 0..8 {
+                self.mem[i] = self.mem[row + i];
+            }
+        }
+    }
+
     // The amount of sprites we found
     fn count(&self) -> usize {
         self.count
@@ -584,8 +841,10 @@ impl Oam {
             // Sprite zero hit for the current scanline was in the previous one
             self.zero_hit_now = self.zero_hit_next;
             self.zero_hit_next = false;
-            self.mem_index = W(0);
             self.smem_index = 0;
+            self.eval_done = false;
+            self.extra_from = None;
+            self.read = self.smem[0];
             return false;
         }
         let cycles = cycles - 1;
@@ -599,64 +858,100 @@ impl Oam {
         } else if cycles < 256 {
             // Read on even cycles
             if cycles % 2 == 0 {
-                self.read = self.mem[self.mem_index.0 as usize];
+                self.read = self.mem[self.address.0 as usize];
                 return false;
             }
-            if self.smem_index % 4 != 0 {
+            if self.eval_done {
+                // Keeps reading Y coordinates but can't copy them anymore
+                self.address += W(4);
+            } else if self.smem_index % 4 != 0 {
                 // Copy the rest of the sprite data when previous was in range
                 self.smem[self.smem_index] = self.read;
                 self.smem_index += 1;
-                self.mem_index += W(1);
+                self.advance(W(1));
+                if self.smem_index == 0x20 && !self.eval_done {
+                    self.extra_from = Some((self.address.0 >> 2) as usize);
+                }
             } else if self.smem_index < 0x20 {
                 // Copy the Y coordinate and test if in range
                 self.smem[self.smem_index] = self.read;
                 // If sprite is in range copy the rest, else go to the next one
-                if self.in_range(scanline, big_sprites) {
-                    // Sprite Zero Hit
-                    if self.mem_index == W(0) {
+                if in_range(self.read, scanline, big_sprites) {
+                    // The first sprite evaluated acts as sprite zero
+                    if cycles == 65 {
                         self.zero_hit_next = true;
                     }
+                    self.sindex[self.smem_index / 4] = self.address.0 >> 2;
                     self.smem_index += 1;
-                    self.mem_index += W(1);
+                    self.advance(W(1));
                 } else {
-                    self.mem_index += W(4);
+                    self.advance(W(4));
                 }
+            } else if in_range(self.read, scanline, big_sprites) {
+                // 8 sprite limit reached, any byte read is taken as a Y
+                // coordinate when looking for sprite overflow
+                self.address += W(1);
+                self.eval_done = true;
+                return true;
             } else {
-                // 8 sprite limit reached, look for sprite overflow
-                if self.mem_index.0 % 4 != 0 && !self.next_sprite {
-                    self.mem_index += W(1);
-                } else if self.in_range(scanline, big_sprites) {
-                    self.mem_index += W(1);
-                    self.next_sprite = false;
-                    // Sprite overflow
-                    return true;
-                } else {
-                    // Emulate hardware bug, add 5 instead of 4
-                    // FIXME: I think there shouldn't be a carry from bit 1 to 2
-                    self.mem_index += W(5);
-                    self.next_sprite = true;
-                }
+                // Hardware bug, both n and m are incremented, m without
+                // carrying into n
+                let n = (self.address & W(0xFC)) + W(4);
+                self.eval_done = n == W(0);
+                self.address = n | (self.address + W(1)) & W(0x03);
             }
         } else if cycles < 320 {
-            // Set index to 0 at start so we can copy to the sprite units.
-            // Set also the count to the amount of sprites we have found
+            // Set the count to the amount of sprites we have found
             if cycles == 256 {
                 self.count = self.smem_index / 4;
-                self.smem_index = 0;
+                if !self.sprite_limit {
+                    self.extra_sprites(scanline, big_sprites, spr_units);
+                }
             }
+            // Each sprite unit reads Y, tile, attributes and then X five times
+            let slot = (cycles - 256) / 8;
+            let byte = (cycles - 256) % 8;
+            self.read = self.smem[slot * 4 + cmp::min(byte, 3)];
             // Fill up to eight sprite units with data
-            if cycles & 4 == 0 && self.smem_index < self.count * 4 {
-                let data = W(self.smem[self.smem_index]);
-                let sprite = &mut spr_units[self.smem_index / 4];
-                sprite.set_sprite_info(self.smem_index % 4, data);
-                self.smem_index += 1;
+            if byte < 4 && slot < self.count {
+                spr_units[slot].set_sprite_info(byte, W(self.read));
+                spr_units[slot].index = self.sindex[slot];
             }
+        } else {
+            self.read = self.smem[0];
         }
         return false;
     }
 
-    fn in_range(&self, scanline: u8, big_sprites: bool) -> bool {
-        let size = if big_sprites {16} else {8};
-        self.read < 0xF0 && self.read + size > scanline && self.read <= scanline
+    // Moves to the next byte, evaluation stops when n wraps around
+    fn advance(&mut self, step: W<u8>) {
+        let next = self.address + step;
+        self.eval_done = next < self.address;
+        self.address = next;
     }
+
+    // Copies the sprites in range the hardware skipped past the eighth
+    // straight to the extra sprite units
+    fn extra_sprites(&mut self, scanline: u8, big_sprites: bool,
+                     spr_units: &mut [Sprite]) {
+        let first = match self.extra_from {
+            Some(first) => first,
+            None => return,
+        };
+        for n in first..OAM_SPRITES {
+            let sprite = &self.mem[n * 4..n * 4 + 4];
+            if in_range(sprite[0], scanline, big_sprites) {
+                for (index, &value) in sprite.iter().enumerate() {
+                    spr_units[self.count].set_sprite_info(index, W(value));
+                }
+                spr_units[self.count].index = n as u8;
+                self.count += 1;
+            }
+        }
+    }
+}
+
+fn in_range(y_pos: u8, scanline: u8, big_sprites: bool) -> bool {
+    let size = if big_sprites {16} else {8};
+    y_pos < 0xF0 && y_pos + size > scanline && y_pos <= scanline
 }
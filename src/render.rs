@@ -1,44 +1,130 @@
-use sdl2::render::Renderer;
-use sdl2::surface::Surface;
-use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Renderer, Texture};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
 use ppu::{Scanline, SCANLINE_WIDTH, SCANLINE_COUNT};
+use palette::Palette;
+use scale::Scaler;
+use display::{Display, Area};
+use ntsc::{Ntsc, NTSC_WIDTH};
 
-const PIXEL_BYTES : usize = 3;
-// RGB NES Palette
-const PALETTE : [[u8; 3]; 0x40] = [
-    [ 84, 84, 84], [  0, 30,116], [  8, 16,144], [ 48,  0,136],
-    [ 68,  0,100], [ 92,  0, 48], [ 84,  4,  0], [ 60, 24,  0],
-    [ 32, 42,  0], [  8, 58,  0], [  0, 64,  0], [  0, 60,  0],
-    [  0, 50, 60], [  0,  0,  0], [  0,  0,  0], [  0,  0,  0],
-    [152,150,152], [  8, 76,196], [ 48, 50,236], [ 92, 30,228],
-    [136, 20,176], [160, 20,100], [152, 34, 32], [120, 60,  0],
-    [ 84, 90,  0], [ 40,114,  0], [  8,124,  0], [  0,118, 40],
-    [  0,102,120], [  0,  0,  0], [  0,  0,  0], [  0,  0,  0],
-    [236,238,236], [ 76,154,236], [120,124,236], [176, 98,236],
-    [228, 84,236], [236, 88,180], [236,106,100], [212,136, 32],
-    [160,170,  0], [116,196,  0], [ 76,208, 32], [ 56,204,108],
-    [ 56,180,204], [ 60, 60, 60], [  0,  0,  0], [  0,  0,  0],
-    [236,238,236], [168,204,236], [188,188,236], [212,178,236],
-    [236,174,236], [236,174,212], [236,180,176], [228,196,144],
-    [204,210,120], [180,222,120], [168,226,144], [152,226,180],
-    [160,214,228], [160,162,160], [  0,  0,  0], [  0,  0,  0],
-];
-
-pub fn render_frame(renderer: &mut Renderer, frame: &[Scanline]) {
-    let (w, h) = (SCANLINE_WIDTH as u32, SCANLINE_COUNT as u32);
-    let mut pixels = [0u8; SCANLINE_WIDTH * SCANLINE_COUNT * PIXEL_BYTES];
-    for y in 0..SCANLINE_COUNT {
-        for x in 0..SCANLINE_WIDTH {
-            let index = (y * SCANLINE_WIDTH + x) * PIXEL_BYTES;
-            let color = PALETTE[frame[y][x] as usize];
-            pixels[index + 0] = color[0];
-            pixels[index + 1] = color[1];
-            pixels[index + 2] = color[2];
+// The texture is XRGB, so packed colors are copied as they are
+const PIXEL_FORMAT : PixelFormatEnum = PixelFormatEnum::RGB888;
+const PIXEL_BYTES  : usize = 4;
+
+// A renderer with one streaming texture that images are copied into, the
+// texture is only created again when the image size changes
+pub struct Screen {
+    renderer        : Renderer<'static>,
+    texture         : Option<(Texture, usize, usize)>,
+    // Packed colors of the frame and its upscaled version
+    image           : Vec<u32>,
+    scaled          : Vec<u32>,
+}
+
+impl Screen {
+    pub fn new(renderer: Renderer<'static>) -> Screen {
+        Screen {
+            renderer        : renderer,
+            texture         : None,
+            image           : Vec::new(),
+            scaled          : Vec::new(),
+        }
+    }
+
+    pub fn render_frame(&mut self, frame: &[Scanline], palette: &Palette,
+                        scaler: Scaler, display: &Display)
+                        -> Result<(), String> {
+        self.image.clear();
+        for line in frame {
+            self.image.extend(line.0.iter()
+                                    .map(|&color| palette.packed(color)));
         }
+        let factor = scaler.factor();
+        let (width, height) = (SCANLINE_WIDTH * factor,
+                               SCANLINE_COUNT * factor);
+        let texture = streaming_texture(&self.renderer, &mut self.texture,
+                                        width, height)?;
+        if scaler == Scaler::None {
+            upload(texture, &self.image, width)?;
+        } else {
+            scaler.scale(&self.image, SCANLINE_WIDTH, SCANLINE_COUNT,
+                         &mut self.scaled);
+            upload(texture, &self.scaled, width)?;
+        }
+        self.present(width, height, display)
+    }
+
+    // Decodes the frame as a TV would see the composite signal
+    pub fn render_ntsc(&mut self, ntsc: &mut Ntsc, frame: &[Scanline],
+                       phase: u8, display: &Display)
+                       -> Result<(), String> {
+        let height = frame.len();
+        let texture = streaming_texture(&self.renderer, &mut self.texture,
+                                        NTSC_WIDTH, height)?;
+        upload(texture, ntsc.filter(frame, phase), NTSC_WIDTH)?;
+        self.present(NTSC_WIDTH, height, display)
     }
-    let surface = Surface::from_data(&mut pixels, w, h, w * PIXEL_BYTES as u32,
-                                     PixelFormatEnum::RGB24).unwrap();
-    let texture = renderer.create_texture_from_surface(surface).unwrap();
-    renderer.copy(&texture, None, None).unwrap();
-    renderer.present();
+
+    // Draws a image of palette colors with the emphasis bits above them
+    pub fn render_colors(&mut self, colors: &[u16], width: usize,
+                         height: usize, palette: &Palette)
+                         -> Result<(), String> {
+        self.image.clear();
+        self.image.extend(colors.iter().map(|&color| palette.packed(color)));
+        let texture = streaming_texture(&self.renderer, &mut self.texture,
+                                        width, height)?;
+        upload(texture, &self.image, width)?;
+        self.present(width, height, &Display::default())
+    }
+
+    fn present(&mut self, width: usize, height: usize, display: &Display)
+               -> Result<(), String> {
+        let target = display.target(self.renderer.output_size()?)
+                            .map(rect);
+        // Black bars around the frame
+        self.renderer.set_draw_color(Color::RGB(0, 0, 0));
+        self.renderer.clear();
+        if let Some((ref texture, _, _)) = self.texture {
+            let source = rect(display.source(width, height));
+            self.renderer.copy(texture, Some(source), target)?;
+        }
+        self.renderer.present();
+        Ok(())
+    }
+}
+
+fn streaming_texture<'a>(renderer: &Renderer,
+                         texture: &'a mut Option<(Texture, usize, usize)>,
+                         width: usize, height: usize)
+                         -> Result<&'a mut Texture, String> {
+    let resized = match *texture {
+        Some((_, w, h)) => w != width || h != height,
+        None => true,
+    };
+    if resized {
+        let created = try_err!(renderer.create_texture_streaming(
+                                   PIXEL_FORMAT, width as u32, height as u32),
+                               "Couldn't create a {}x{} texture", width,
+                               height);
+        *texture = Some((created, width, height));
+    }
+    match *texture {
+        Some((ref mut texture, _, _)) => Ok(texture),
+        None => unreachable!(),
+    }
+}
+
+fn rect(area: Area) -> Rect {
+    Rect::new(area.x, area.y, area.width, area.height)
+}
+
+fn upload(texture: &mut Texture, image: &[u32], width: usize)
+          -> Result<(), String> {
+    texture.with_lock(None, |pixels, pitch| {
+        for (row, line) in pixels.chunks_mut(pitch).zip(image.chunks(width)) {
+            for (pixel, color) in row.chunks_mut(PIXEL_BYTES).zip(line) {
+                pixel.copy_from_slice(&color.to_ne_bytes());
+            }
+        }
+    })
 }
@@ -0,0 +1,304 @@
+// Pixel art upscalers, colors are 0x00RRGGBB
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaler {
+    None,
+    // Eric's Pixel Expansion, Scale2x gets the same pixels another way
+    Epx,
+    Scale2x,
+    Scale3x,
+}
+
+// In the order the hotkey goes through them
+const SCALERS : [Scaler; 4] = [
+    Scaler::None, Scaler::Epx, Scaler::Scale2x, Scaler::Scale3x,
+];
+
+pub fn parse_scaler(name: &str) -> Result<Scaler, String> {
+    match SCALERS.iter().find(|scaler| scaler.name() == name) {
+        Some(&scaler) => Ok(scaler),
+        None => err!("Invalid scaler {}", name),
+    }
+}
+
+impl Scaler {
+    pub fn name(&self) -> String {
+        match *self {
+            Scaler::None    => "none".to_string(),
+            Scaler::Epx     => "epx".to_string(),
+            Scaler::Scale2x => "scale2x".to_string(),
+            Scaler::Scale3x => "scale3x".to_string(),
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match *self {
+            Scaler::None    => 1,
+            Scaler::Epx     => 2,
+            Scaler::Scale2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    pub fn next(&self) -> Scaler {
+        let index = SCALERS.iter().position(|scaler| scaler == self)
+                           .unwrap_or(0);
+        SCALERS[(index + 1) % SCALERS.len()]
+    }
+
+    // Output is factor times the width and height of the image
+    pub fn scale(&self, image: &[u32], width: usize, height: usize,
+                 output: &mut Vec<u32>) {
+        let factor = self.factor();
+        output.resize(width * height * factor * factor, 0);
+        // Big enough for the 3x3 block of Scale3x, kept off the heap
+        let mut block = [0; 9];
+        for y in 0..height {
+            for x in 0..width {
+                let kernel = kernel(image, width, height, x, y);
+                match *self {
+                    Scaler::None    => block[0] = kernel[4],
+                    Scaler::Epx     => epx(&kernel, &mut block),
+                    Scaler::Scale2x => scale2x(&kernel, &mut block),
+                    Scaler::Scale3x => scale3x(&kernel, &mut block),
+                }
+                for row in 0..factor {
+                    let start = (y * factor + row) * width * factor +
+                                x * factor;
+                    output[start..start + factor].copy_from_slice(
+                        &block[row * factor..(row + 1) * factor]);
+                }
+            }
+        }
+    }
+}
+
+// The pixel at x, y with the edges repeated past the image
+fn pixel(image: &[u32], width: usize, height: usize, x: isize, y: isize)
+         -> u32 {
+    let x = x.max(0).min(width as isize - 1) as usize;
+    let y = y.max(0).min(height as isize - 1) as usize;
+    image[y * width + x]
+}
+
+// 3x3 pixels around x, y
+fn kernel(image: &[u32], width: usize, height: usize, x: usize, y: usize)
+          -> [u32; 9] {
+    let mut kernel = [0; 9];
+    for (index, color) in kernel.iter_mut().enumerate() {
+        let (dx, dy) = (index as isize % 3 - 1, index as isize / 3 - 1);
+        *color = pixel(image, width, height, x as isize + dx, y as isize + dy);
+    }
+    kernel
+}
+
+// Each corner takes the color of the neighbors around it when they match
+// and the other two don't
+fn epx(kernel: &[u32; 9], block: &mut [u32]) {
+    let (a, c, p, b, d) = (kernel[1], kernel[3], kernel[4], kernel[5],
+                           kernel[7]);
+    for color in block[..4].iter_mut() {
+        *color = p;
+    }
+    if c == a && c != d && a != b {
+        block[0] = a;
+    }
+    if a == b && a != c && b != d {
+        block[1] = b;
+    }
+    if d == c && d != b && c != a {
+        block[2] = c;
+    }
+    if b == d && b != a && d != c {
+        block[3] = d;
+    }
+}
+
+fn scale2x(kernel: &[u32; 9], block: &mut [u32]) {
+    let (b, d, e, f, h) = (kernel[1], kernel[3], kernel[4], kernel[5],
+                           kernel[7]);
+    if b != h && d != f {
+        block[0] = if d == b {d} else {e};
+        block[1] = if b == f {f} else {e};
+        block[2] = if d == h {d} else {e};
+        block[3] = if h == f {f} else {e};
+    } else {
+        for color in block.iter_mut() {
+            *color = e;
+        }
+    }
+}
+
+fn scale3x(kernel: &[u32; 9], block: &mut [u32]) {
+    let (a, b, c, d, e, f, g, h, i) = (kernel[0], kernel[1], kernel[2],
+                                       kernel[3], kernel[4], kernel[5],
+                                       kernel[6], kernel[7], kernel[8]);
+    for color in block.iter_mut() {
+        *color = e;
+    }
+    if b != h && d != f {
+        block[0] = if d == b {d} else {e};
+        block[1] = if (d == b && e != c) || (b == f && e != a) {b} else {e};
+        block[2] = if b == f {f} else {e};
+        block[3] = if (d == b && e != g) || (d == h && e != a) {d} else {e};
+        block[5] = if (b == f && e != i) || (h == f && e != c) {f} else {e};
+        block[6] = if d == h {d} else {e};
+        block[7] = if (d == h && e != i) || (h == f && e != g) {h} else {e};
+        block[8] = if h == f {f} else {e};
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use scale::{Scaler, SCALERS, parse_scaler};
+    use utils::Random;
+
+    const B : u32 = 0x000000;
+    const W : u32 = 0xFFFFFF;
+
+    // A staircase, the usual worst case for nearest neighbor
+    const STAIRS : [u32; 16] = [
+        W, B, B, B,
+        W, W, B, B,
+        W, W, W, B,
+        W, W, W, W,
+    ];
+
+    const REFERENCE_WIDTH : usize = 32;
+    const REFERENCE_HEIGHT : usize = 24;
+
+    fn scale(scaler: Scaler, image: &[u32], width: usize, height: usize)
+             -> Vec<u32> {
+        let mut output = Vec::new();
+        scaler.scale(image, width, height, &mut output);
+        output
+    }
+
+    // Four colors at random, enough of them match to take every branch
+    fn reference_frame() -> Vec<u32> {
+        let mut random = Random::new(47);
+        let colors = [B, W, 0xFF0000, 0x0000FF];
+        (0..REFERENCE_WIDTH * REFERENCE_HEIGHT)
+            .map(|_| colors[(random.next_u64() >> 62) as usize])
+            .collect()
+    }
+
+    // The reference rules of the AdvanceMAME Scale2x and Scale3x docs,
+    // from the neighbors of E with the edges repeated
+    //   A B C
+    //   D E F
+    //   G H I
+    fn reference(factor: usize, image: &[u32]) -> Vec<u32> {
+        let (width, height) = (REFERENCE_WIDTH, REFERENCE_HEIGHT);
+        let at = |x: isize, y: isize| {
+            let x = x.max(0).min(width as isize - 1) as usize;
+            let y = y.max(0).min(height as isize - 1) as usize;
+            image[y * width + x]
+        };
+        let mut output = vec![0; width * height * factor * factor];
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (x as isize, y as isize);
+                let (a, b, c) = (at(x0 - 1, y0 - 1), at(x0, y0 - 1),
+                                 at(x0 + 1, y0 - 1));
+                let (d, e, f) = (at(x0 - 1, y0), at(x0, y0), at(x0 + 1, y0));
+                let (g, h, i) = (at(x0 - 1, y0 + 1), at(x0, y0 + 1),
+                                 at(x0 + 1, y0 + 1));
+                let db = d == b && b != f && d != h;
+                let bf = b == f && b != d && f != h;
+                let dh = d == h && d != b && h != f;
+                let hf = h == f && d != h && b != f;
+                let block = if factor == 2 {
+                    vec![if db {d} else {e}, if bf {f} else {e},
+                         if dh {d} else {e}, if hf {f} else {e}]
+                } else {
+                    vec![if db {d} else {e},
+                         if (db && e != c) || (bf && e != a) {b} else {e},
+                         if bf {f} else {e},
+                         if (db && e != g) || (dh && e != a) {d} else {e},
+                         e,
+                         if (bf && e != i) || (hf && e != c) {f} else {e},
+                         if dh {d} else {e},
+                         if (dh && e != i) || (hf && e != g) {h} else {e},
+                         if hf {f} else {e}]
+                };
+                for (n, &color) in block.iter().enumerate() {
+                    let (row, column) = (n / factor, n % factor);
+                    output[(y * factor + row) * width * factor +
+                           x * factor + column] = color;
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_names() {
+        for &scaler in SCALERS.iter() {
+            assert_eq!(parse_scaler(&scaler.name()), Ok(scaler));
+        }
+        assert!(parse_scaler("hq2x").is_err());
+    }
+
+    #[test]
+    fn test_reference_frame() {
+        let image = reference_frame();
+        let (width, height) = (REFERENCE_WIDTH, REFERENCE_HEIGHT);
+        let scale2x = reference(2, &image);
+        let doubled: Vec<u32> = (0..scale2x.len()).map(|n| {
+            let (x, y) = (n % (width * 2) / 2, n / (width * 2) / 2);
+            image[y * width + x]
+        }).collect();
+        assert!(scale2x != doubled);
+        assert!(scale(Scaler::Scale2x, &image, width, height) == scale2x);
+        assert!(scale(Scaler::Epx, &image, width, height) == scale2x);
+        assert!(scale(Scaler::Scale3x, &image, width, height) ==
+                reference(3, &image));
+    }
+
+    #[test]
+    fn test_flat() {
+        for &scaler in SCALERS.iter() {
+            let factor = scaler.factor();
+            let output = scale(scaler, &[0x123456; 12], 4, 3);
+            assert!(output.len() == 12 * factor * factor, "{:?}", scaler);
+            assert!(output.iter().all(|&color| color == 0x123456),
+                    "{:?} changed a flat image", scaler);
+        }
+    }
+
+    #[test]
+    fn test_scale2x() {
+        // The inner corners of the steps get filled in
+        let expected = [
+            W, W, B, B, B, B, B, B,
+            W, W, W, B, B, B, B, B,
+            W, W, W, B, B, B, B, B,
+            W, W, W, W, W, B, B, B,
+            W, W, W, W, W, B, B, B,
+            W, W, W, W, W, W, W, B,
+            W, W, W, W, W, W, W, W,
+            W, W, W, W, W, W, W, W,
+        ];
+        assert!(scale(Scaler::Scale2x, &STAIRS, 4, 4)[..] == expected[..]);
+    }
+
+    #[test]
+    fn test_scale3x() {
+        let expected = [
+            W, W, W, B, B, B, B, B, B, B, B, B,
+            W, W, W, W, B, B, B, B, B, B, B, B,
+            W, W, W, W, B, B, B, B, B, B, B, B,
+            W, W, W, W, W, B, B, B, B, B, B, B,
+            W, W, W, W, W, W, B, B, B, B, B, B,
+            W, W, W, W, W, W, W, B, B, B, B, B,
+            W, W, W, W, W, W, W, W, B, B, B, B,
+            W, W, W, W, W, W, W, W, W, B, B, B,
+            W, W, W, W, W, W, W, W, W, W, W, B,
+            W, W, W, W, W, W, W, W, W, W, W, W,
+            W, W, W, W, W, W, W, W, W, W, W, W,
+            W, W, W, W, W, W, W, W, W, W, W, W,
+        ];
+        assert!(scale(Scaler::Scale3x, &STAIRS, 4, 4)[..] == expected[..]);
+    }
+}
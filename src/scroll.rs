@@ -13,6 +13,8 @@ const HORIZONTAL_MASK  : W<u16> = W(0x041F);
 const VERTICAL_MASK    : W<u16> = W(0x7BE0);
 const BG_OFFSET_FLAG   : W<u8>  = W(0x10);
 const INCREMENT_FLAG   : W<u8>  = W(0x04);
+// Dots until the second $2006 write reaches the address
+const ADDRESS_DELAY    : u8     = 3;
 
 /* Coarse is 5 upper bits of a scroll (Byte selection)
  * Fine is 3 lower bits of a scroll (Pixel selection inside byte)
@@ -29,6 +31,12 @@ pub struct Scroll {
     write_flag  : bool,
     bg_offset   : W<u16>,
     increment   : W<u16>,
+    // Dots left for the temporal address copy after a $2006 write
+    delay       : u8,
+    // Increments from $2007 accesses while rendering, done at the end of
+    // the dot unless rendering already did them
+    glitch_x    : bool,
+    glitch_y    : bool,
 }
 
 impl Default for Scroll {
@@ -40,6 +48,9 @@ impl Default for Scroll {
             write_flag  : false,
             bg_offset   : W(0),
             increment   : W(1),
+            delay       : 0,
+            glitch_x    : false,
+            glitch_y    : false,
         }
     }
 }
@@ -56,14 +67,19 @@ impl Scroll {
         self.write_flag = false;
     }
 
+    // The address is kept while scroll and ppuctrl bits are cleared
+    pub fn soft_reset(&mut self) {
+        let address = self.address;
+        *self = Scroll::default();
+        self.address = address;
+    }
+
     pub fn get_address(&mut self, rendering: bool) -> W<u16> {
         // The lower 14 bits compose a full address
         let ret = self.address & W(0x3FFF);
-        // FIXME: While rendering if increment_y or increment_coarse_x
-        // were called in the same dot we shouldn't increment them here again
         if rendering {
-            self.increment_coarse_x();
-            self.increment_y();
+            self.glitch_x = true;
+            self.glitch_y = true;
         } else {
             self.address += self.increment;
         }
@@ -73,13 +89,34 @@ impl Scroll {
     pub fn set_address(&mut self, value: W<u8>) {
         if self.write_flag {
             set_low_byte!(self.temporal, value);
-            self.address = self.temporal;
+            self.delay = ADDRESS_DELAY;
         } else {
             set_high_byte!(self.temporal, value & W(0x3F));
         }
         self.write_flag = !self.write_flag;
     }
 
+    // The current address without incrementing it
+    pub fn peek_address(&self) -> W<u16> {
+        self.address & W(0x3FFF)
+    }
+
+    // Applies the delayed updates at the end of every dot
+    pub fn cycle(&mut self) {
+        if self.glitch_x {
+            self.increment_coarse_x();
+        }
+        if self.glitch_y {
+            self.increment_y();
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            if self.delay == 0 {
+                self.address = self.temporal;
+            }
+        }
+    }
+
     pub fn get_nametable_address(&self) -> W<u16> {
         // The lower 12 bits are the position in the nametables
         NAMETABLE_OFFSET | self.address & W(0xFFF)
@@ -126,6 +163,15 @@ impl Scroll {
         self.temporal = self.temporal & !COARSE_X_MASK | (W16!(value) >> 3);
     }
 
+    // Scroll set through $2000 and $2005, in the 512x480 nametable space
+    pub fn get_position(&self) -> (usize, usize) {
+        let temporal = self.temporal.0 as usize;
+        let x = (temporal & 0x1F) << 3 | self.fine_x.0 as usize |
+                (temporal & 0x400) >> 2;
+        let y = (temporal >> 5 & 0x1F) << 3 | temporal >> 12 & 0x7;
+        (x, y + (temporal >> 11 & 1) * 240)
+    }
+
     pub fn get_fine_x(&mut self) -> u8 {
         self.fine_x.0
     }
@@ -136,6 +182,7 @@ impl Scroll {
     }
 
     pub fn increment_coarse_x(&mut self) {
+        self.glitch_x = false;
         // If coarse_x is about to overflow
         if self.address & COARSE_X_MASK == COARSE_X_MASK {
             // Wrap coarse_x to 0 and go to next nametable
@@ -147,6 +194,7 @@ impl Scroll {
     }
 
     pub fn increment_y(&mut self) {
+        self.glitch_y = false;
         let mut scroll_y = self.get_scroll_y() + W(1);
         // If coarse_y overflowed into the attribute table
         if scroll_y == W(0xF0) {
@@ -175,3 +223,54 @@ impl fmt::Debug for Scroll {
                self.write_flag, self.bg_offset.0, self.increment.0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use scroll::{Scroll, ADDRESS_DELAY};
+    use std::num::Wrapping as W;
+
+    fn scroll_at(high: u8, low: u8) -> Scroll {
+        let mut scroll = Scroll::default();
+        scroll.set_address(W(high));
+        scroll.set_address(W(low));
+        for _ in 0..ADDRESS_DELAY {
+            scroll.cycle();
+        }
+        scroll
+    }
+
+    #[test]
+    pub fn test_address_delay() {
+        let mut scroll = scroll_at(0x21, 0x08);
+        scroll.set_address(W(0x23));
+        scroll.set_address(W(0xC0));
+        for _ in 1..ADDRESS_DELAY {
+            scroll.cycle();
+            assert_eq!(scroll.peek_address(), W(0x2108));
+        }
+        scroll.cycle();
+        assert_eq!(scroll.peek_address(), W(0x23C0));
+    }
+
+    #[test]
+    pub fn test_rendering_access() {
+        // Outside rendering the access adds the PPUCTRL increment
+        let mut scroll = scroll_at(0x21, 0x08);
+        assert_eq!(scroll.get_address(false), W(0x2108));
+        assert_eq!(scroll.peek_address(), W(0x2109));
+        // While rendering it is a coarse X and a Y increment at the end of
+        // the dot, coarse X 8 to 9 and fine Y 0 to 1
+        let mut scroll = scroll_at(0x21, 0x08);
+        scroll.get_address(true);
+        assert_eq!(scroll.peek_address(), W(0x2108));
+        scroll.cycle();
+        assert_eq!(scroll.peek_address(), W(0x3109));
+        // Merged with the ones rendering does on the same dot
+        let mut scroll = scroll_at(0x21, 0x08);
+        scroll.get_address(true);
+        scroll.increment_coarse_x();
+        scroll.increment_y();
+        scroll.cycle();
+        assert_eq!(scroll.peek_address(), W(0x3109));
+    }
+}
@@ -0,0 +1,233 @@
+// nes
+use nes::Nes;
+use ppu::Scanline;
+use backend::{Video, Audio, Input, Hotkey};
+use input::{get_keys, get_hotkeys};
+use render::Screen;
+use ntsc::{Ntsc, NtscSetup};
+use palette::{Palette, PaletteSetup};
+use scale::{Scaler, parse_scaler};
+use display::{Display, parse_aspect, parse_overscan};
+use viewer::{Viewers, View, parse_view};
+use apu::SAMPLE_RATE;
+use enums::Region;
+// std
+use std::error::Error;
+// SDL2
+use sdl2::{Sdl, EventPump};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::render::Renderer;
+
+pub const OPTIONS : &'static str =
+    "[--view=nametables|patterns|palette|oam|events]... \
+     [--view-line=SCANLINE] \
+     [--ntsc] [--ntsc-sharpness=N] [--ntsc-fringing=N] \
+     [--palette=FILE | [--palette-hue=DEGREES] \
+     [--palette-saturation=N] [--palette-contrast=N] \
+     [--palette-brightness=N] [--palette-gamma=N]] \
+     [--save-palette=FILE] \
+     [--scaler=none|epx|scale2x|scale3x] \
+     [--aspect=stretch|square|8:7|4:3] [--integer-scale] \
+     [--overscan=ntsc|TOP,BOTTOM,LEFT,RIGHT]";
+
+fn sdl(width: u32, height: u32)
+       -> Result<(Renderer<'static>, Sdl, EventPump), Box<Error>> {
+    let context = sdl2::init()?;
+    let video = context.video()?;
+    let window = video.window("Warnes", width, height)
+                      .position_centered().resizable().build()?;
+    let renderer = window.renderer().present_vsync().build()?;
+    let event_pump = context.event_pump()?;
+    Ok((renderer, context, event_pump))
+}
+
+fn parse_ntsc_value(value: &str) -> Result<f32, String> {
+    match value.parse() {
+        Ok(value) if value >= -1.0 && value <= 1.0 => Ok(value),
+        _ => err!("Invalid NTSC setting {}, it goes from -1 to 1", value),
+    }
+}
+
+fn parse_palette_value(value: &str) -> Result<f32, String> {
+    Ok(try_err!(value.parse(), "Invalid palette setting {}", value))
+}
+
+// Window, keyboard and sound through SDL
+pub struct SdlBackend {
+    context         : Sdl,
+    event_pump      : EventPump,
+    screen          : Screen,
+    viewers         : Viewers,
+    views           : Vec<View>,
+    palette         : Palette,
+    ntsc            : Option<Ntsc>,
+    scaler          : Scaler,
+    display         : Display,
+    // Opened with the first samples
+    audio           : Option<AudioQueue<i16>>,
+}
+
+impl SdlBackend {
+    pub fn new(args: &[&str]) -> Result<SdlBackend, String> {
+        let mut views = Vec::new();
+        // Start of vblank, the frame is done and the game hasn't changed
+        // it yet
+        let mut view_line = 241;
+        let mut ntsc = false;
+        let mut ntsc_setup = NtscSetup::default();
+        let mut palette_file = None;
+        let mut palette_setup = None;
+        let mut save_palette = None;
+        let mut scaler = Scaler::None;
+        let mut display = Display::default();
+        for &arg in args {
+            if arg == "--ntsc" {
+                ntsc = true;
+            } else if arg.starts_with("--ntsc-sharpness=") {
+                ntsc = true;
+                ntsc_setup.sharpness =
+                    parse_ntsc_value(&arg["--ntsc-sharpness=".len()..])?;
+            } else if arg.starts_with("--ntsc-fringing=") {
+                ntsc = true;
+                ntsc_setup.fringing =
+                    parse_ntsc_value(&arg["--ntsc-fringing=".len()..])?;
+            } else if arg.starts_with("--palette=") {
+                palette_file = Some(&arg["--palette=".len()..]);
+            } else if arg.starts_with("--save-palette=") {
+                save_palette = Some(&arg["--save-palette=".len()..]);
+            } else if arg.starts_with("--palette-") {
+                let mut parts = arg["--palette-".len()..].splitn(2, '=');
+                let (name, value) = match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => (name, value),
+                    _ => return err!("Invalid parameter {}", arg),
+                };
+                let value = parse_palette_value(value)?;
+                let setup = palette_setup.get_or_insert(PaletteSetup::default());
+                match name {
+                    "hue"        => setup.hue = value,
+                    "saturation" => setup.saturation = value,
+                    "contrast"   => setup.contrast = value,
+                    "brightness" => setup.brightness = value,
+                    "gamma"      => setup.gamma = value,
+                    _            => return err!("Invalid parameter {}", arg),
+                }
+            } else if arg == "--integer-scale" {
+                display.integer_scale = true;
+            } else if arg.starts_with("--aspect=") {
+                display.aspect = parse_aspect(&arg["--aspect=".len()..])?;
+            } else if arg.starts_with("--overscan=") {
+                display.overscan =
+                    parse_overscan(&arg["--overscan=".len()..])?;
+            } else if arg.starts_with("--scaler=") {
+                scaler = parse_scaler(&arg["--scaler=".len()..])?;
+            } else if arg.starts_with("--view=") {
+                views.push(parse_view(&arg["--view=".len()..])?);
+            } else if arg.starts_with("--view-line=") {
+                let line = &arg["--view-line=".len()..];
+                view_line = try_err!(line.parse(), "Invalid scanline {}", line);
+            } else {
+                return err!("Invalid parameter {}", arg);
+            }
+        }
+        let palette = match (palette_file, palette_setup) {
+            (Some(_), Some(_)) =>
+                return err!("A palette file can't be used with palette \
+                             settings"),
+            (Some(path), None) => Palette::load(path)?,
+            (None, Some(setup)) => Palette::generate(setup),
+            (None, None) => Palette::default(),
+        };
+        if let Some(path) = save_palette {
+            palette.save(path)?;
+        }
+        let (width, height) = display.window_size(scaler.factor() as u32);
+        let (renderer, context, event_pump) =
+            try_err!(sdl(width, height), "Couldn't init SDL");
+        let video = context.video()?;
+        let viewers = Viewers::new(&video, &views, view_line)?;
+        Ok(
+            SdlBackend {
+                context         : context,
+                event_pump      : event_pump,
+                screen          : Screen::new(renderer),
+                viewers         : viewers,
+                views           : views,
+                palette         : palette,
+                ntsc            : if ntsc {Some(Ntsc::new(ntsc_setup))}
+                                  else {None},
+                scaler          : scaler,
+                display         : display,
+                audio           : None,
+            }
+        )
+    }
+}
+
+impl Video for SdlBackend {
+    fn start(&mut self, nes: &mut Nes) {
+        if self.views.contains(&View::Events) {
+            nes.start_events();
+        }
+        // PAL signals alternate the phase every line, there's no decoder
+        if self.ntsc.is_some() && nes.region() != Region::Ntsc {
+            println!("Warning: the NTSC filter is off for {:?}",
+                     nes.region());
+            self.ntsc = None;
+        }
+    }
+
+    fn frame(&mut self, frame: &[Scanline], _number: u64, phase: u8)
+             -> Result<(), String> {
+        match self.ntsc {
+            Some(ref mut ntsc) =>
+                self.screen.render_ntsc(ntsc, frame, phase, &self.display),
+            None => self.screen.render_frame(frame, &self.palette,
+                                             self.scaler, &self.display),
+        }
+    }
+
+    fn cycle(&mut self, nes: &Nes) -> Result<(), String> {
+        self.viewers.cycle(nes, &self.palette)
+    }
+}
+
+impl Audio for SdlBackend {
+    fn queue(&mut self, samples: &[i16]) -> Result<(), String> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        if self.audio.is_none() {
+            let spec = AudioSpecDesired {
+                freq        : Some(SAMPLE_RATE as i32),
+                channels    : Some(1),
+                samples     : None,
+            };
+            let audio = self.context.audio()?.open_queue(None, &spec)?;
+            audio.resume();
+            self.audio = Some(audio);
+        }
+        match self.audio {
+            Some(ref audio) if !audio.queue(samples) =>
+                err!("Couldn't queue audio samples"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Input for SdlBackend {
+    // The scaler and the viewer palette are handled here
+    fn poll(&mut self, keys: &mut [[u8; 8]; 2], hotkeys: &mut Vec<Hotkey>)
+            -> bool {
+        for hotkey in get_hotkeys(&mut self.event_pump) {
+            match hotkey {
+                Hotkey::ViewerPalette => self.viewers.next_palette(),
+                Hotkey::Scaler => {
+                    self.scaler = self.scaler.next();
+                    println!("Scaler {}", self.scaler.name());
+                },
+                _ => hotkeys.push(hotkey),
+            }
+        }
+        get_keys(&mut self.event_pump, keys)
+    }
+}
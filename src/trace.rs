@@ -0,0 +1,145 @@
+// nes
+use cpu::{Cpu, Operation};
+use ppu::Ppu;
+use mem::Memory as Mem;
+// std
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::num::Wrapping as W;
+use std::path::Path;
+
+// Logs every instruction in the Nintendulator format nestest.log uses
+pub struct Trace {
+    output          : Box<dyn Write>,
+    // Inclusive PC ranges to log, everything if empty
+    ranges          : Vec<(u16, u16)>,
+}
+
+impl Trace {
+    pub fn new(output: Box<dyn Write>, ranges: Vec<(u16, u16)>) -> Trace {
+        Trace {
+            output          : output,
+            ranges          : ranges,
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P, ranges: Vec<(u16, u16)>)
+                                  -> Result<Trace, String> {
+        let file = try_err!(File::create(path), "Couldn't create trace file");
+        Ok(Trace::new(Box::new(BufWriter::new(file)), ranges))
+    }
+
+    // Called right after the CPU fetched an opcode, before the PPU catches up
+    pub fn log(&mut self, cpu: &Cpu, ppu: &Ppu, memory: &Mem)
+               -> io::Result<()> {
+        let pc = cpu.execution().pc;
+        if !self.ranges.is_empty() &&
+           !self.ranges.iter().any(|&(start, end)| start <= pc.0 && pc.0 <= end) {
+            return Ok(());
+        }
+        let regs = cpu.registers();
+        let operation = Operation::from_address(memory, pc);
+        let size = operation.inst.mode.size.0;
+        let bytes: Vec<String> = (0..size).map(|i| memory.peek(pc + W(i)))
+                                          .map(|b| format!("{:02X}", b.0))
+                                          .collect();
+        let (scanline, dot) = ppu.position();
+        writeln!(self.output,
+                 "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+                 pc.0, bytes.join(" "),
+                 if operation.is_official() {' '} else {'*'},
+                 disassemble(&operation, pc, cpu, memory),
+                 regs.A.0, regs.X.0, regs.Y.0, regs.P.0 | 0x20, regs.SP.0,
+                 scanline, dot, cpu.cycle_count() - 1)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// Parses START-END in hex, with an optional $ on each side
+pub fn parse_range(range: &str) -> Result<(u16, u16), String> {
+    let parse = |value: &str| {
+        let value = if value.starts_with('$') {&value[1..]} else {value};
+        u16::from_str_radix(value, 16)
+    };
+    let mut parts = range.splitn(2, '-');
+    match (parts.next().map(&parse), parts.next().map(&parse)) {
+        (Some(Ok(start)), Some(Ok(end))) if start <= end => Ok((start, end)),
+        _ => err!("Invalid PC range {}", range),
+    }
+}
+
+fn zero_page_word(memory: &Mem, pointer: u8) -> u16 {
+    let low = memory.peek(W(pointer as u16)).0 as u16;
+    let high = memory.peek(W(pointer.wrapping_add(1) as u16)).0 as u16;
+    high << 8 | low
+}
+
+// The operand with its effective address and the value there before running
+fn disassemble(operation: &Operation, pc: W<u16>, cpu: &Cpu, memory: &Mem)
+               -> String {
+    let inst = operation.inst;
+    let regs = cpu.registers();
+    let (x, y) = (regs.X.0, regs.Y.0);
+    let operand = operation.operand.0;
+    let zero_page = operand as u8;
+    let value = |address: u16| memory.peek(W(address)).0;
+    let name = match inst.name {
+        "sal" => "ASL A".to_string(),
+        "sar" => "LSR A".to_string(),
+        "ral" => "ROL A".to_string(),
+        "rar" => "ROR A".to_string(),
+        // nestest.log uses the other common name
+        "isc" => "ISB".to_string(),
+        name  => name.to_uppercase(),
+    };
+    match (inst.name, inst.mode.name) {
+        (_, "imp") => name,
+        (_, "imm") => format!("{} #${:02X}", name, zero_page),
+        (_, "rel") => {
+            let target = pc + W(2) + W(zero_page as i8 as u16);
+            format!("{} ${:04X}", name, target.0)
+        },
+        ("jmp", "abs") | ("jsr", _) => format!("{} ${:04X}", name, operand),
+        (_, "abs") => format!("{} ${:04X} = {:02X}", name, operand,
+                              value(operand)),
+        (_, "abx") | (_, "aby") => {
+            let (index, register) = if inst.mode.name == "abx" {(x, 'X')}
+                                    else {(y, 'Y')};
+            let address = operand.wrapping_add(index as u16);
+            format!("{} ${:04X},{} @ {:04X} = {:02X}", name, operand,
+                    register, address, value(address))
+        },
+        (_, "ind") => {
+            // The pointer high byte doesn't cross pages
+            let high = operand & 0xFF00 | operand.wrapping_add(1) & 0xFF;
+            let target = (value(high) as u16) << 8 | value(operand) as u16;
+            format!("{} (${:04X}) = {:04X}", name, operand, target)
+        },
+        (_, "idx") => {
+            let pointer = zero_page.wrapping_add(x);
+            let address = zero_page_word(memory, pointer);
+            format!("{} (${:02X},X) @ {:02X} = {:04X} = {:02X}", name,
+                    zero_page, pointer, address, value(address))
+        },
+        (_, "idy") => {
+            let base = zero_page_word(memory, zero_page);
+            let address = base.wrapping_add(y as u16);
+            format!("{} (${:02X}),Y = {:04X} @ {:04X} = {:02X}", name,
+                    zero_page, base, address, value(address))
+        },
+        (_, "zpg") => format!("{} ${:02X} = {:02X}", name, zero_page,
+                              value(zero_page as u16)),
+        (_, "zpx") | (_, "zpy") => {
+            let (index, register) = if inst.mode.name == "zpx" {(x, 'X')}
+                                    else {(y, 'Y')};
+            let address = zero_page.wrapping_add(index);
+            format!("{} ${:02X},{} @ {:02X} = {:02X}", name, zero_page,
+                    register, address, value(address as u16))
+        },
+        _ => name,
+    }
+}
@@ -0,0 +1,310 @@
+// nes
+use nes::Nes;
+use render::Screen;
+use palette::Palette;
+use events::EventKind;
+// std
+use std::error::Error;
+use std::num::Wrapping as W;
+// SDL2
+use sdl2::VideoSubsystem;
+use sdl2::render::Renderer;
+
+const NAMETABLE_WIDTH   : usize = 256;
+const NAMETABLE_HEIGHT  : usize = 240;
+const TILE_SIZE         : usize = 8;
+const SWATCH_SIZE       : usize = 16;
+// Color used for the scroll rectangle
+const SCROLL_COLOR      : u8 = 0x16;
+// Event map, dots of every scanline up to the longest frame
+const MAP_WIDTH         : usize = 341;
+const MAP_HEIGHT        : usize = 312;
+const VISIBLE_COLOR     : u8 = 0x2D;
+const BLANK_COLOR       : u8 = 0x0F;
+// Writes to $2000-$2007, $2002 is never seen as it's read only
+const REGISTER_COLORS   : [u8; 8] = [
+    0x16, 0x2A, 0x0F, 0x28, 0x24, 0x21, 0x27, 0x30,
+];
+const OAMDMA_COLOR      : u8 = 0x14;
+const IRQ_COLOR         : u8 = 0x11;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    Nametables,
+    Patterns,
+    Palette,
+    Oam,
+    Events,
+}
+
+impl View {
+    // Image size and window scale
+    fn size(&self) -> (usize, usize, u32) {
+        match *self {
+            View::Nametables => (NAMETABLE_WIDTH * 2, NAMETABLE_HEIGHT * 2, 1),
+            View::Patterns   => (256, 128, 3),
+            View::Palette    => (SWATCH_SIZE * 16, SWATCH_SIZE * 2, 2),
+            // Eight rows of eight sprites, tall enough for 8x16 sprites
+            View::Oam        => (TILE_SIZE * 8, TILE_SIZE * 16, 4),
+            View::Events     => (MAP_WIDTH, MAP_HEIGHT, 2),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match *self {
+            View::Nametables => "Nametables",
+            View::Patterns   => "Pattern tables",
+            View::Palette    => "Palette",
+            View::Oam        => "OAM",
+            View::Events     => "PPU events",
+        }
+    }
+}
+
+pub fn parse_view(name: &str) -> Result<View, String> {
+    match name {
+        "nametables" => Ok(View::Nametables),
+        "patterns"   => Ok(View::Patterns),
+        "palette"    => Ok(View::Palette),
+        "oam"        => Ok(View::Oam),
+        "events"     => Ok(View::Events),
+        _            => err!("Invalid view {}", name),
+    }
+}
+
+// Colors in the same format as the frame, without emphasis
+struct Image {
+    width           : usize,
+    height          : usize,
+    pixels          : Vec<u16>,
+}
+
+impl Image {
+    fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y * self.width + x] = color as u16;
+    }
+}
+
+struct Window {
+    view            : View,
+    screen          : Screen,
+    image           : Image,
+}
+
+pub struct Viewers {
+    windows         : Vec<Window>,
+    // Scanline where the views are refreshed
+    scanline        : usize,
+    last_scanline   : usize,
+    // Palette for the pattern tables, 0-3 background and 4-7 sprites
+    palette         : usize,
+}
+
+impl Viewers {
+    pub fn new(video: &VideoSubsystem, views: &[View], scanline: usize)
+               -> Result<Viewers, String> {
+        let mut windows = Vec::new();
+        for &view in views {
+            let renderer = try_err!(create_renderer(video, view),
+                                    "Couldn't create the {} window",
+                                    view.title());
+            let (width, height, _) = view.size();
+            windows.push(Window {
+                view            : view,
+                screen          : Screen::new(renderer),
+                image           : Image {
+                    width           : width,
+                    height          : height,
+                    pixels          : vec![0; width * height],
+                },
+            });
+        }
+        Ok(
+            Viewers {
+                windows         : windows,
+                scanline        : scanline,
+                last_scanline   : scanline,
+                palette         : 0,
+            }
+        )
+    }
+
+    pub fn next_palette(&mut self) {
+        self.palette = (self.palette + 1) % 8;
+        println!("Pattern table palette {}", self.palette);
+    }
+
+    // Refreshes the views when the PPU enters the chosen scanline
+    pub fn cycle(&mut self, nes: &Nes, palette: &Palette)
+                 -> Result<(), String> {
+        if self.windows.is_empty() {
+            return Ok(());
+        }
+        let (scanline, _) = nes.ppu().position();
+        let refresh = scanline == self.scanline &&
+                      self.last_scanline != self.scanline;
+        self.last_scanline = scanline;
+        if !refresh {
+            return Ok(());
+        }
+        for window in &mut self.windows {
+            let image = &mut window.image;
+            match window.view {
+                View::Nametables => draw_nametables(nes, image),
+                View::Patterns   => draw_patterns(nes, image, self.palette),
+                View::Palette    => draw_palette(nes, image),
+                View::Oam        => draw_oam(nes, image),
+                View::Events     => draw_events(nes, image),
+            }
+            window.screen.render_colors(&image.pixels, image.width,
+                                        image.height, palette)?;
+        }
+        Ok(())
+    }
+}
+
+fn create_renderer(video: &VideoSubsystem, view: View)
+                   -> Result<Renderer<'static>, Box<Error>> {
+    let (width, height, scale) = view.size();
+    let window = video.window(view.title(), width as u32 * scale,
+                              height as u32 * scale)
+                      .resizable().build()?;
+    Ok(window.renderer().build()?)
+}
+
+// Draws an 8x8 tile, the color 0 pixels show the backdrop
+fn draw_tile(nes: &Nes, image: &mut Image, x: usize, y: usize,
+             address: W<u16>, palette: usize, flip: (bool, bool)) {
+    let colors = nes.ppu().palette();
+    for row in 0..TILE_SIZE {
+        let line = if flip.1 {TILE_SIZE - 1 - row} else {row};
+        let low = nes.ppu_peek(address + W(line as u16)).0;
+        let high = nes.ppu_peek(address + W(line as u16 + 8)).0;
+        for column in 0..TILE_SIZE {
+            let bit = if flip.0 {column} else {TILE_SIZE - 1 - column};
+            let index = (low >> bit & 1 | (high >> bit & 1) << 1) as usize;
+            let color = if index == 0 {colors[0]}
+                        else {colors[palette * 4 + index]};
+            image.set(x + column, y + row, color);
+        }
+    }
+}
+
+// The four nametables as the PPU addresses them, mirrors included
+fn draw_nametables(nes: &Nes, image: &mut Image) {
+    let table = nes.ppu().background_table();
+    for nametable in 0..4 {
+        let base = W(0x2000 + nametable as u16 * 0x400);
+        let (left, top) = ((nametable & 1) * NAMETABLE_WIDTH,
+                           (nametable >> 1) * NAMETABLE_HEIGHT);
+        for tile_y in 0..NAMETABLE_HEIGHT / TILE_SIZE {
+            for tile_x in 0..NAMETABLE_WIDTH / TILE_SIZE {
+                let name = W((tile_y * 32 + tile_x) as u16);
+                let attribute = W((tile_y / 4 * 8 + tile_x / 4) as u16);
+                let name = nes.ppu_peek(base + name);
+                let attribute = nes.ppu_peek(base + W(0x3C0) + attribute);
+                let shift = (tile_y & 2) << 1 | tile_x & 2;
+                let palette = (attribute.0 >> shift & 3) as usize;
+                draw_tile(nes, image, left + tile_x * TILE_SIZE,
+                          top + tile_y * TILE_SIZE,
+                          table | W(name.0 as u16) << 4, palette,
+                          (false, false));
+            }
+        }
+    }
+    // The visible area, it wraps around the edges
+    let (width, height) = (image.width, image.height);
+    let (scroll_x, scroll_y) = nes.ppu().scroll_position();
+    // Coarse Y can point into the attributes, past the bottom nametable
+    let scroll_y = scroll_y % height;
+    for x in 0..NAMETABLE_WIDTH {
+        let x = (scroll_x + x) % width;
+        image.set(x, scroll_y, SCROLL_COLOR);
+        image.set(x, (scroll_y + NAMETABLE_HEIGHT - 1) % height,
+                  SCROLL_COLOR);
+    }
+    for y in 0..NAMETABLE_HEIGHT {
+        let y = (scroll_y + y) % height;
+        image.set(scroll_x, y, SCROLL_COLOR);
+        image.set((scroll_x + NAMETABLE_WIDTH - 1) % width, y,
+                  SCROLL_COLOR);
+    }
+}
+
+// Both pattern tables side by side
+fn draw_patterns(nes: &Nes, image: &mut Image, palette: usize) {
+    for tile in 0..0x200 {
+        let (table, index) = (tile / 0x100, tile % 0x100);
+        draw_tile(nes, image, table * 128 + index % 16 * TILE_SIZE,
+                  index / 16 * TILE_SIZE, W(tile as u16) << 4, palette,
+                  (false, false));
+    }
+}
+
+// Background palettes on the top row and sprite ones on the bottom
+fn draw_palette(nes: &Nes, image: &mut Image) {
+    let colors = nes.ppu().palette();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let index = y / SWATCH_SIZE * 16 + x / SWATCH_SIZE;
+            image.set(x, y, colors[index]);
+        }
+    }
+}
+
+// Every OAM entry decoded with the current sprite size and table
+fn draw_oam(nes: &Nes, image: &mut Image) {
+    let ppu = nes.ppu();
+    let oam = ppu.oam();
+    let backdrop = ppu.palette()[0];
+    for pixel in image.pixels.iter_mut() {
+        *pixel = backdrop as u16;
+    }
+    for sprite in 0..64 {
+        let tile = oam[sprite * 4 + 1] as u16;
+        let attributes = oam[sprite * 4 + 2];
+        let flip = (attributes & 0x40 != 0, attributes & 0x80 != 0);
+        let palette = 4 + (attributes & 3) as usize;
+        let (x, y) = (sprite % 8 * TILE_SIZE, sprite / 8 * TILE_SIZE * 2);
+        if ppu.sprite_big() {
+            let top = W((tile & 1) << 12 | (tile & 0xFE) << 4);
+            let (first, second) = if flip.1 {(top + W(16), top)}
+                                  else {(top, top + W(16))};
+            draw_tile(nes, image, x, y, first, palette, flip);
+            draw_tile(nes, image, x, y + TILE_SIZE, second, palette, flip);
+        } else {
+            let address = ppu.sprite_table() | W(tile << 4);
+            draw_tile(nes, image, x, y, address, palette, flip);
+        }
+    }
+}
+
+// Timing map of the last frame, each event is a 3x3 mark on its dot
+fn draw_events(nes: &Nes, image: &mut Image) {
+    let scanlines = nes.region().scanlines();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let visible = y < 240 && x >= 1 && x <= 256;
+            image.set(x, y, if visible {VISIBLE_COLOR} else {BLANK_COLOR});
+        }
+    }
+    let events = match nes.events() {
+        Some(events) => events.frame(),
+        None => return,
+    };
+    for event in events {
+        let color = match event.kind {
+            EventKind::Register(0x4014) => OAMDMA_COLOR,
+            EventKind::Register(address) =>
+                REGISTER_COLORS[(address & 0x7) as usize],
+            EventKind::MapperIrq => IRQ_COLOR,
+        };
+        for y in event.scanline.saturating_sub(1)..event.scanline + 2 {
+            for x in event.dot.saturating_sub(1)..event.dot + 2 {
+                if x < image.width && y < scanlines {
+                    image.set(x, y, color);
+                }
+            }
+        }
+    }
+}